@@ -20,6 +20,7 @@ void main() {
 #ifdef FRAGMENT_SHADER
 uniform sampler2D u_texture;
 uniform sampler2D u_mask;
+uniform vec4 u_color;
 
 void main() {
     if (texture2D(u_mask, v_mask_uv).a != 1.0) {
@@ -29,5 +30,6 @@ void main() {
     if (gl_FragColor.a != 1.0) {
         discard;
     }
+    gl_FragColor.rgb *= u_color.rgb;
 }
 #endif
\ No newline at end of file
@@ -1,6 +1,6 @@
 use super::*;
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct CameraConfig {
     pub distance: f32,
     pub attack: f32,
@@ -9,14 +9,14 @@ pub struct CameraConfig {
     pub speed: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct CrabAnimation {
     pub legs_freq: f32,
     pub legs_amp: f32,
     pub z: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct WaveConfig {
     pub dir: vec2<f32>,
     pub freq: f32,
@@ -25,34 +25,189 @@ pub struct WaveConfig {
     pub speed: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct WaterConfig {
     pub color: Rgba<f32>,
     pub z: f32,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
 pub struct SharkConfig {
+    pub behaviors: Vec<SharkBehavior>,
+    pub aggression_growth: f64,
     pub attack_prob: f64,
     pub count: usize,
     pub depth: f32,
     pub speed: f32,
     pub extra_move_radius: f32,
+    pub grab_radius: f32,
+    pub grab_windup: f32,
+    pub grab_prob: f64,
 }
 
-#[derive(Deserialize)]
+#[derive(Deserialize, Clone)]
+pub struct RespawnConfig {
+    pub lives: usize,
+    pub delay: f32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RoundConfig {
+    pub time: f32,
+    pub shrink_interval: f32,
+    pub attack_prob_growth: f64,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PerTileKind<T> {
+    pub plain: T,
+    pub reinforced: T,
+    pub slippery: T,
+    pub bouncy: T,
+}
+
+impl<T> PerTileKind<T> {
+    pub fn get(&self, kind: map::TileKind) -> &T {
+        match kind {
+            map::TileKind::Plain => &self.plain,
+            map::TileKind::Reinforced => &self.reinforced,
+            map::TileKind::Slippery => &self.slippery,
+            map::TileKind::Bouncy => &self.bouncy,
+        }
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct TilesConfig {
+    pub bouncy_push_k: f32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RaftGenConfig {
+    pub layouts: Vec<map::Layout>,
+    pub ring_hole: f32,
+    pub islands: usize,
+    pub island_size: i32,
+    pub noise_scale: f64,
+    pub noise_threshold: f64,
+    pub kinds: PerTileKind<f64>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RepairConfig {
+    pub time: f32,
+    pub distance: f32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct ItemsConfig {
+    pub spawn_interval: f32,
+    pub max_count: usize,
+    pub lifetime: f32,
+    pub drift_speed: f32,
+    pub pickup_radius: f32,
+    pub effect_time: f32,
+    pub quick_dash_k: f32,
+    pub long_dash_k: f32,
+    pub repellent_radius: f32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct PoopConfig {
+    pub lifetime: f32,
+    pub stun_time: f32,
+    pub hit_radius: f32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct GullConfig {
+    pub dive_cooldown: f32,
+    pub dive_time: f32,
+    pub dive_radius: f32,
+    pub dive_push: f32,
+    pub plank_cooldown: f32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct MutatorsConfig {
+    pub chance: f64,
+    pub low_gravity_k: f32,
+    pub dash_k: f32,
+    pub slippery_weight: f64,
+    pub extra_sharks: usize,
+    pub tiny_raft_size: i32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct DashChargeConfig {
+    pub max_time: f32,
+    pub min_k: f32,
+    pub max_k: f32,
+    pub color: Rgba<f32>,
+    pub easing: Easing,
+}
+
+impl DashChargeConfig {
+    pub fn distance_k(&self, charge: f32) -> f32 {
+        let t = if charge.is_nan() {
+            0.0
+        } else {
+            (charge / self.max_time).clamp(0.0, 1.0)
+        };
+        self.min_k + (self.max_k - self.min_k) * t
+    }
+}
+
+#[derive(Deserialize, Clone)]
+pub struct BraceConfig {
+    pub time: f32,
+    pub cooldown: f32,
+    pub push_k: f32,
+    pub reflect_k: f32,
+    pub color: Rgba<f32>,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct RaftPhysicsConfig {
+    pub sag_per_crab: f32,
+    pub tilt_angle: f32,
+    pub response: f32,
+    pub sink_depth: f32,
+    pub sink_time: f32,
+    pub sink_speed: f32,
+    pub fragment_drift_speed: f32,
+    pub fragment_lifetime: f32,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct SnapshotConfig {
+    pub rate: f32,
+    pub min_rate: f32,
+    pub max_bytes_per_second: f32,
+    pub upload_rate: f32,
+    pub ping_interval: f32,
+    pub render_delay: f64,
+    pub max_extrapolation: f64,
+}
+
+#[derive(Deserialize, Clone)]
 pub struct SpectatorConfig {
     pub rotate_speed: f32,
     pub winner_zoom_in_distance: f32,
+    pub zoom_stiffness: f32,
+    pub zoom_damping: f32,
 }
 
-#[derive(Deserialize, geng::asset::Load)]
+#[derive(Deserialize, Clone, geng::asset::Load)]
 #[load(serde = "toml")]
 pub struct Config {
     pub kill_timer: f64,
     pub survival_points: Vec<usize>,
     pub elimination_points: usize,
     pub spectator: SpectatorConfig,
+    pub snapshot: SnapshotConfig,
+    pub respawn: RespawnConfig,
+    pub round: RoundConfig,
     pub music_volume: f64,
     pub slide_speed: f32,
     pub push_distance_rem_k: f32,
@@ -63,6 +218,8 @@ pub struct Config {
     pub attack_time: f32,
     pub dash_cooldown: f32,
     pub dash_distance: f32,
+    pub dash_charge: DashChargeConfig,
+    pub brace: BraceConfig,
     pub push_distance: f32,
     pub restart_timer: f32,
     pub water: WaterConfig,
@@ -78,6 +235,14 @@ pub struct Config {
     pub seagull_rotate_speed: f32,
 
     pub raft_size: i32,
+    pub raft_gen: RaftGenConfig,
+    pub tiles: TilesConfig,
+    pub raft_physics: RaftPhysicsConfig,
+    pub repair: RepairConfig,
+    pub items: ItemsConfig,
+    pub poop: PoopConfig,
+    pub gull: GullConfig,
+    pub mutators: MutatorsConfig,
     pub shark: SharkConfig,
 }
 
@@ -118,6 +283,9 @@ pub struct Assets {
     pub splash: Rc<pog_paint::Model>,
     pub destroy: Rc<pog_paint::Model>,
     pub raft_tile: pog_paint::Model,
+    pub reinforced_tile: pog_paint::Model,
+    pub slippery_tile: pog_paint::Model,
+    pub bouncy_tile: pog_paint::Model,
     pub damage: Rc<pog_paint::Model>,
     pub push: Rc<pog_paint::Model>,
     pub falling_poop: pog_paint::Model,
@@ -129,6 +297,17 @@ pub struct Assets {
     pub music: geng::Sound,
 }
 
+impl Assets {
+    pub fn tile(&self, kind: map::TileKind) -> &pog_paint::Model {
+        match kind {
+            map::TileKind::Plain => &self.raft_tile,
+            map::TileKind::Reinforced => &self.reinforced_tile,
+            map::TileKind::Slippery => &self.slippery_tile,
+            map::TileKind::Bouncy => &self.bouncy_tile,
+        }
+    }
+}
+
 async fn load_custom(manager: &geng::asset::Manager) -> anyhow::Result<HashMap<String, Crab>> {
     let custom_dir = run_dir().join("assets").join("custom");
     let custom_dir = &custom_dir;
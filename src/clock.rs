@@ -0,0 +1,65 @@
+use super::*;
+
+pub struct ServerClock {
+    local: Timer,
+    pig_sent: Option<f64>,
+    offset: Option<f64>,
+    rtt: Option<f64>,
+    jitter: f64,
+}
+
+impl ServerClock {
+    /// Weight of each new sample, also used for the pings the server shows
+    pub const SMOOTHING: f64 = 0.1;
+
+    pub fn new() -> Self {
+        Self {
+            local: Timer::new(),
+            pig_sent: None,
+            offset: None,
+            rtt: None,
+            jitter: 0.0,
+        }
+    }
+
+    fn local_time(&self) -> f64 {
+        self.local.elapsed().as_secs_f64()
+    }
+
+    pub fn pig_sent(&mut self) {
+        self.pig_sent = Some(self.local_time());
+    }
+
+    /// Server stamps `Pog` while handling our `Pig`, so it's about half a round trip old
+    pub fn pog(&mut self, server_time: f64) {
+        let now = self.local_time();
+        let rtt = self.pig_sent.take().map(|sent| now - sent);
+        if let Some(rtt) = rtt {
+            if let Some(last) = self.rtt {
+                self.jitter += ((rtt - last).abs() - self.jitter) * Self::SMOOTHING;
+            }
+            self.rtt = Some(rtt);
+        }
+        let sample = server_time + rtt.unwrap_or(0.0) / 2.0 - now;
+        self.offset = Some(match self.offset {
+            Some(offset) => offset + (sample - offset) * Self::SMOOTHING,
+            None => sample,
+        });
+    }
+
+    pub fn rtt(&self) -> Option<f64> {
+        self.rtt
+    }
+
+    pub fn jitter(&self) -> f64 {
+        self.jitter
+    }
+
+    pub fn offset(&self) -> f64 {
+        self.offset.unwrap_or(0.0)
+    }
+
+    pub fn now(&self) -> f64 {
+        self.local_time() + self.offset.unwrap_or(0.0)
+    }
+}
@@ -0,0 +1,85 @@
+use super::*;
+
+use serde::{de::DeserializeOwned, Deserializer, Serializer};
+
+/// A message already encoded by us, geng only carries the bytes so their count is exact
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Packet(Vec<u8>);
+
+impl Packet {
+    pub fn encode<T: Serialize>(message: &T) -> Self {
+        Self(bincode::serialize(message).expect("messages always serialize"))
+    }
+    pub fn decode<T: DeserializeOwned>(&self) -> bincode::Result<T> {
+        bincode::deserialize(&self.0)
+    }
+    pub fn size(&self) -> usize {
+        self.0.len()
+    }
+}
+
+/// Hundredths of a unit in an `i16` per component, plenty for anything near the raft
+pub mod quantized_vec3 {
+    use super::*;
+
+    const SCALE: f32 = 100.0;
+
+    pub fn serialize<S: Serializer>(value: &vec3<f32>, serializer: S) -> Result<S::Ok, S::Error> {
+        // `as` saturates out of range values and turns NaN into 0
+        [value.x, value.y, value.z]
+            .map(|x| (x * SCALE).round() as i16)
+            .serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<vec3<f32>, D::Error> {
+        let [x, y, z] = <[i16; 3]>::deserialize(deserializer)?;
+        Ok(vec3(x, y, z).map(|x| x as f32 / SCALE))
+    }
+}
+
+/// Full turn mapped onto a `u16`
+pub mod quantized_angle {
+    use super::*;
+
+    use std::f32::consts::TAU;
+
+    const STEPS: f32 = 65536.0;
+
+    pub fn serialize<S: Serializer>(value: &Angle<f32>, serializer: S) -> Result<S::Ok, S::Error> {
+        let turns = value.as_radians().rem_euclid(TAU) / TAU;
+        ((turns * STEPS).round() as u32 as u16).serialize(serializer)
+    }
+
+    pub fn deserialize<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Angle<f32>, D::Error> {
+        let steps = u16::deserialize(deserializer)?;
+        Ok(Angle::from_radians(steps as f32 / STEPS * TAU))
+    }
+}
+
+#[test]
+fn test_quantized_pos() {
+    let pos = Pos {
+        pos: vec3(12.345, -7.5, 0.001),
+        rot: Angle::from_degrees(-90.0),
+        vel: vec3(1000.0, 0.0, -3.0),
+    };
+    let decoded: Pos = bincode::deserialize(&bincode::serialize(&pos).unwrap()).unwrap();
+    assert!((decoded.pos - pos.pos).len() < 0.01);
+    assert!((decoded.rot.as_degrees() - 270.0).abs() < 0.01);
+    assert!(decoded.vel.x == i16::MAX as f32 / 100.0);
+    assert!(bincode::serialized_size(&pos).unwrap() == 14);
+}
+
+#[test]
+fn test_packet() {
+    let packet = Packet::encode(&ClientMessage::Attack(vec3(1.0, 2.0, 0.0), 0.5));
+    // Variant tag, three quantized components and the charge
+    assert!(packet.size() == 4 + 6 + 4);
+    let ClientMessage::Attack(target, charge) = packet.decode().unwrap() else {
+        panic!("decoded a different message");
+    };
+    assert!(target == vec3(1.0, 2.0, 0.0) && charge == 0.5);
+    assert!(Packet(packet.0[..4].to_vec())
+        .decode::<ClientMessage>()
+        .is_err());
+}
@@ -1,114 +1,376 @@
 use super::*;
 
-pub struct Interpolated<T> {
-    a: T,
-    b: T,
-    c: T,
-    d: T,
-    t: f32,
-    interpolation_time: f32,
-}
-
-// f(t) = A * t^3 + B * t^2 + C * t + D
-// f(0) = p1
-// f'(0) = v1 * IT
-// f(1) = p2
-// f'(1) = v2 * IT
-
-// D = p1
-// C = v1 * IT
-// A + B + C + D = p2
-// 3A + 2B + C = v2 * IT
-
-// A = p2 - B - C - D
-// 3 (p2 - B - C - D) + 2B + C = v2 * IT
-// 3 p2 - 3B - 3C - 3D + 2B + C = v2 * IT
-// B = 3p2 - 2C - 3D - v2 * IT
-
-pub trait Zero {
+use std::collections::VecDeque;
+
+/// A value that can be blended, everything here is built from these few linear operations
+pub trait Interpolate: Copy {
     const ZERO: Self;
-}
+    fn sum(self, other: Self) -> Self;
+    /// `self - other`
+    fn difference(self, other: Self) -> Self;
+    fn scaled(self, k: f32) -> Self;
 
-impl Zero for f32 {
-    const ZERO: Self = 0.0;
+    fn lerp(a: Self, b: Self, t: f32) -> Self {
+        a.sum(b.difference(a).scaled(t))
+    }
+    /// Cubic from `p1` to `p2` over `t` in `0..=1`, with tangents `m1` and `m2` per unit of `t`
+    fn hermite(p1: Self, m1: Self, p2: Self, m2: Self, t: f32) -> Self {
+        // Offsets from `p1` only, so wrapping types like angles never get summed whole
+        let (t2, t3) = (t * t, t * t * t);
+        p1.sum(p2.difference(p1).scaled(-2.0 * t3 + 3.0 * t2))
+            .sum(m1.scaled(t3 - 2.0 * t2 + t))
+            .sum(m2.scaled(t3 - t2))
+    }
+    fn hermite_derivative(p1: Self, m1: Self, p2: Self, m2: Self, t: f32) -> Self {
+        let t2 = t * t;
+        p2.difference(p1)
+            .scaled(-6.0 * t2 + 6.0 * t)
+            .sum(m1.scaled(3.0 * t2 - 4.0 * t + 1.0))
+            .sum(m2.scaled(3.0 * t2 - 2.0 * t))
+    }
+    fn ease(a: Self, b: Self, t: f32, easing: Easing) -> Self {
+        Self::lerp(a, b, easing.apply(t))
+    }
 }
 
-impl Zero for vec2<f32> {
-    const ZERO: Self = vec2::ZERO;
+macro_rules! impl_interpolate_linear {
+    ($($t:ty => $zero:expr),* $(,)?) => {
+        $(
+            impl Interpolate for $t {
+                const ZERO: Self = $zero;
+                fn sum(self, other: Self) -> Self {
+                    self + other
+                }
+                fn difference(self, other: Self) -> Self {
+                    self - other
+                }
+                fn scaled(self, k: f32) -> Self {
+                    self * k
+                }
+            }
+        )*
+    };
 }
 
-impl Zero for vec3<f32> {
-    const ZERO: Self = vec3::ZERO;
+impl_interpolate_linear! {
+    f32 => 0.0,
+    vec2<f32> => vec2::ZERO,
+    vec3<f32> => vec3::ZERO,
 }
 
-impl Zero for Angle<f32> {
+/// Differences go the short way around, so blending 359 and 1 degrees passes through 0
+impl Interpolate for Angle<f32> {
     const ZERO: Self = Angle::ZERO;
+    fn sum(self, other: Self) -> Self {
+        self + other
+    }
+    fn difference(self, other: Self) -> Self {
+        angle_delta(other, self)
+    }
+    fn scaled(self, k: f32) -> Self {
+        self * k
+    }
+}
+
+impl Interpolate for Rgba<f32> {
+    const ZERO: Self = Rgba::TRANSPARENT_BLACK;
+    fn sum(self, other: Self) -> Self {
+        Rgba::new(
+            self.r + other.r,
+            self.g + other.g,
+            self.b + other.b,
+            self.a + other.a,
+        )
+    }
+    fn difference(self, other: Self) -> Self {
+        Rgba::new(
+            self.r - other.r,
+            self.g - other.g,
+            self.b - other.b,
+            self.a - other.a,
+        )
+    }
+    fn scaled(self, k: f32) -> Self {
+        Rgba::new(self.r * k, self.g * k, self.b * k, self.a * k)
+    }
+}
+
+impl Interpolate for Pos {
+    const ZERO: Self = Pos {
+        pos: vec3::ZERO,
+        vel: vec3::ZERO,
+        rot: Angle::ZERO,
+    };
+    fn sum(self, other: Self) -> Self {
+        Pos {
+            pos: self.pos.sum(other.pos),
+            vel: self.vel.sum(other.vel),
+            rot: self.rot.sum(other.rot),
+        }
+    }
+    fn difference(self, other: Self) -> Self {
+        Pos {
+            pos: self.pos.difference(other.pos),
+            vel: self.vel.difference(other.vel),
+            rot: self.rot.difference(other.rot),
+        }
+    }
+    fn scaled(self, k: f32) -> Self {
+        Pos {
+            pos: self.pos.scaled(k),
+            vel: self.vel.scaled(k),
+            rot: self.rot.scaled(k),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Easing {
+    Linear,
+    QuadIn,
+    QuadOut,
+    QuadInOut,
+    CubicOut,
+    SineInOut,
+}
+
+impl Easing {
+    /// Maps `t` in `0..=1` (clamped) onto the curve, keeping both ends in place
+    pub fn apply(self, t: f32) -> f32 {
+        let t = if t.is_nan() { 0.0 } else { t.clamp(0.0, 1.0) };
+        match self {
+            Self::Linear => t,
+            Self::QuadIn => t * t,
+            Self::QuadOut => 1.0 - (1.0 - t) * (1.0 - t),
+            Self::QuadInOut => {
+                if t < 0.5 {
+                    2.0 * t * t
+                } else {
+                    1.0 - 2.0 * (1.0 - t) * (1.0 - t)
+                }
+            }
+            Self::CubicOut => 1.0 - (1.0 - t).powi(3),
+            Self::SineInOut => (1.0 - (t * std::f32::consts::PI).cos()) / 2.0,
+        }
+    }
 }
 
-const MIN_INTERPOLATION_TIME: f32 = 0.05;
+/// Damped spring chasing a target that keeps moving, where an easing curve would restart every time
+pub struct Spring<T> {
+    pub value: T,
+    pub velocity: T,
+    pub stiffness: f32,
+    pub damping: f32,
+}
 
-impl<T: Mul<f32, Output = T> + Add<Output = T> + Sub<Output = T> + Copy + Zero> Interpolated<T> {
-    pub fn new(p: T, v: T) -> Self {
-        let interpolation_time = MIN_INTERPOLATION_TIME;
+impl<T: Interpolate> Spring<T> {
+    pub fn new(value: T, stiffness: f32, damping: f32) -> Self {
         Self {
-            a: T::ZERO,
-            b: T::ZERO,
-            c: v * interpolation_time,
-            d: p,
-            t: 0.0,
-            interpolation_time,
+            value,
+            velocity: T::ZERO,
+            stiffness,
+            damping,
         }
     }
-    pub fn teleport(&mut self, p: T, v: T) {
-        self.a = T::ZERO;
-        self.b = T::ZERO;
-        self.c = v * self.interpolation_time;
-        self.d = p;
-        self.t = 0.0;
-    }
-    pub fn server_update(&mut self, p2: T, v2: T) {
-        let p1 = self.get();
-        let v1 = self.get_derivative();
-        let interpolation_time = (self.t * 1.5).max(MIN_INTERPOLATION_TIME);
-        // let p2 = p2 + v2 * interpolation_time; // Prediction
-        let d = p1;
-        let c = v1 * interpolation_time;
-        let b = p2 * 3.0 - c * 2.0 - d * 3.0 - v2 * interpolation_time;
-        let a = p2 - b - c - d;
-        *self = Self {
-            a,
-            b,
-            c,
-            d,
-            t: 0.0,
-            interpolation_time,
-        };
+    pub fn update(&mut self, target: T, delta_time: f32) -> T {
+        let acceleration = target
+            .difference(self.value)
+            .scaled(self.stiffness)
+            .difference(self.velocity.scaled(self.damping));
+        self.velocity = self.velocity.sum(acceleration.scaled(delta_time));
+        self.value = self.value.sum(self.velocity.scaled(delta_time));
+        self.value
     }
-    pub fn update(&mut self, delta_time: f32) {
-        self.t += delta_time;
+}
+
+/// Timestamped samples that are read back at a time in the past,
+/// so there is usually a known sample on both sides to interpolate between
+pub struct SnapshotBuffer<T> {
+    samples: VecDeque<(f64, T, T)>,
+    max_extrapolation: f64,
+}
+
+impl<T: Interpolate> SnapshotBuffer<T> {
+    const MAX_SAMPLES: usize = 64;
+
+    pub fn new(max_extrapolation: f64) -> Self {
+        Self {
+            samples: VecDeque::new(),
+            max_extrapolation,
+        }
+    }
+    pub fn push(&mut self, time: f64, value: T, derivative: T) {
+        let index = self.samples.partition_point(|&(t, _, _)| t <= time);
+        self.samples.insert(index, (time, value, derivative));
+        while self.samples.len() > Self::MAX_SAMPLES {
+            self.samples.pop_front();
+        }
     }
-    pub fn get(&self) -> T {
-        let t = (self.t / self.interpolation_time).min(1.0);
-        self.a * t.powi(3) + self.b * t.sqr() + self.c * t + self.d
+    pub fn clear(&mut self) {
+        self.samples.clear();
+    }
+    /// Drops samples that are not needed to read at `time` or later
+    pub fn prune(&mut self, time: f64) {
+        while self.samples.len() > 1 && self.samples[1].0 <= time {
+            self.samples.pop_front();
+        }
+    }
+    /// Value and derivative at `time`, extrapolating at most `max_extrapolation` past the last sample
+    pub fn get(&self, time: f64) -> Option<(T, T)> {
+        let &(first_time, first, first_derivative) = self.samples.front()?;
+        if time <= first_time {
+            return Some((first, first_derivative));
+        }
+        let index = self.samples.partition_point(|&(t, _, _)| t <= time);
+        if let Some(&(t2, b, _)) = self.samples.get(index) {
+            let (t1, a, _) = self.samples[index - 1];
+            let k = ((time - t1) / (t2 - t1)) as f32;
+            let derivative = b.difference(a).scaled(1.0 / (t2 - t1) as f32);
+            return Some((T::lerp(a, b, k), derivative));
+        }
+        let &(last_time, last, derivative) = self.samples.back().unwrap();
+        let dt = time - last_time;
+        if dt > self.max_extrapolation {
+            Some((
+                last.sum(derivative.scaled(self.max_extrapolation as f32)),
+                T::ZERO,
+            ))
+        } else {
+            Some((last.sum(derivative.scaled(dt as f32)), derivative))
+        }
     }
-    pub fn get_derivative(&self) -> T {
-        let t = (self.t / self.interpolation_time).min(1.0);
-        (self.a * 3.0 * t.sqr() + self.b * 2.0 * t + self.c) * (1.0 / self.interpolation_time)
+}
+
+/// Signed turn from `from` to `to` the short way around, in `[-PI, PI)`
+pub fn angle_delta(from: Angle<f32>, to: Angle<f32>) -> Angle<f32> {
+    use std::f32::consts::{PI, TAU};
+    Angle::from_radians(((to - from).as_radians() + PI).rem_euclid(TAU) - PI)
+}
+
+impl SnapshotBuffer<Angle<f32>> {
+    /// Unwraps `angle` next to its neighbouring sample so interpolation takes the shortest arc,
+    /// and uses the turn since that sample as angular velocity
+    pub fn push_angle(&mut self, time: f64, angle: Angle<f32>) {
+        let index = self.samples.partition_point(|&(t, _, _)| t <= time);
+        let neighbour = match index {
+            0 => self.samples.front(),
+            _ => self.samples.get(index - 1),
+        };
+        let Some(&(neighbour_time, neighbour_angle, _)) = neighbour else {
+            self.push(time, angle, Angle::ZERO);
+            return;
+        };
+        let delta = angle_delta(neighbour_angle, angle);
+        let dt = (time - neighbour_time) as f32;
+        let angular_velocity = if dt == 0.0 {
+            Angle::ZERO
+        } else {
+            delta * (1.0 / dt)
+        };
+        self.push(time, neighbour_angle + delta, angular_velocity);
     }
 }
 
 #[test]
 fn test_interpolation() {
-    let mut i = Interpolated::new(0.0, 1.0);
-    assert!(i.get() == 0.0);
-    assert!(i.get_derivative() == 1.0);
-    i.server_update(1.0, 1.0);
-    assert!(i.get() == 0.0);
-    assert!(i.get_derivative() == 1.0);
-    i.update(MIN_INTERPOLATION_TIME);
-    assert!(i.get() == 1.0);
-    assert!(i.get_derivative() == 1.0);
-    i.update(-MIN_INTERPOLATION_TIME / 2.0);
-    assert!(i.get() == 0.5);
-    assert!(i.get_derivative() == 1.0);
+    let mut buffer = SnapshotBuffer::<f32>::new(1.0);
+    buffer.push(0.0, 0.0, 1.0);
+    assert!(buffer.get(0.0) == Some((0.0, 1.0)));
+    buffer.push(1.0, 1.0, 1.0);
+    assert!(buffer.get(0.0) == Some((0.0, 1.0)));
+    assert!(buffer.get(1.0) == Some((1.0, 1.0)));
+    assert!(buffer.get(0.5) == Some((0.5, 1.0)));
+}
+
+#[test]
+fn test_snapshot_buffer() {
+    let close = |a: f32, b: f32| (a - b).abs() < 1e-4;
+    let mut buffer = SnapshotBuffer::new(0.25);
+    assert!(buffer.get(0.0).is_none());
+    buffer.push(1.0, 10.0, 1.0);
+    buffer.push(0.0, 0.0, 1.0);
+    let (value, derivative) = buffer.get(0.5).unwrap();
+    assert!(close(value, 5.0) && close(derivative, 10.0));
+    assert!(close(buffer.get(-1.0).unwrap().0, 0.0));
+    let (value, derivative) = buffer.get(1.1).unwrap();
+    assert!(close(value, 10.1) && close(derivative, 1.0));
+    let (value, derivative) = buffer.get(5.0).unwrap();
+    assert!(close(value, 10.25) && close(derivative, 0.0));
+    buffer.prune(1.5);
+    assert!(close(buffer.get(0.5).unwrap().0, 10.0));
+}
+
+#[test]
+fn test_angle_interpolation() {
+    let close = |a: Angle<f32>, b: f32| (a.as_degrees() - b).abs() < 1e-2;
+    let deg = Angle::<f32>::from_degrees;
+    assert!(close(angle_delta(deg(359.0), deg(1.0)), 2.0));
+    assert!(close(angle_delta(deg(1.0), deg(359.0)), -2.0));
+    assert!(close(angle_delta(deg(-170.0), deg(170.0)), -20.0));
+
+    let mut buffer = SnapshotBuffer::new(1.0);
+    buffer.push_angle(0.0, deg(359.0));
+    buffer.push_angle(1.0, deg(1.0));
+    let (angle, angular_velocity) = buffer.get(0.5).unwrap();
+    assert!(close(angle, 360.0));
+    assert!(close(angular_velocity, 2.0));
+    let (angle, angular_velocity) = buffer.get(1.5).unwrap();
+    assert!(close(angle, 362.0));
+    assert!(close(angular_velocity, 2.0));
+
+    // Late sample gets unwrapped against the one before it too
+    buffer.push_angle(0.75, deg(0.5));
+    assert!(close(buffer.get(0.75).unwrap().0, 360.5));
+}
+
+#[test]
+fn test_interpolate_types() {
+    let black: Rgba<f32> = Rgba::new(0.0, 0.0, 0.0, 1.0);
+    let color = Interpolate::lerp(black, Rgba::new(1.0, 0.5, 0.0, 0.0), 0.5);
+    assert!(color.r == 0.5 && color.g == 0.25 && color.b == 0.0 && color.a == 0.5);
+    let scale: vec3<f32> = vec3(1.0, 1.0, 1.0);
+    assert!(Interpolate::lerp(scale, vec3(3.0, 1.0, 0.0), 0.5) == vec3(2.0, 1.0, 0.5));
+    let rot: Angle<f32> = Angle::from_degrees(350.0);
+    let rot = Interpolate::lerp(rot, Angle::from_degrees(10.0), 0.5);
+    assert!((rot.as_degrees() - 360.0).abs() < 1e-3);
+    assert!((f32::hermite(0.0, 1.0, 1.0, 1.0, 0.5) - 0.5).abs() < 1e-6);
+    let (from, to) = (Angle::from_degrees(350.0), Angle::from_degrees(10.0));
+    let rot = Angle::hermite(from, Angle::ZERO, to, Angle::ZERO, 0.5);
+    assert!((rot.as_degrees() - 360.0).abs() < 1e-3);
+    let spin = Angle::hermite_derivative(from, Angle::ZERO, to, Angle::ZERO, 0.5);
+    assert!((spin.as_degrees() - 30.0).abs() < 1e-3);
+}
+
+#[test]
+fn test_easing() {
+    for easing in [
+        Easing::Linear,
+        Easing::QuadIn,
+        Easing::QuadOut,
+        Easing::QuadInOut,
+        Easing::CubicOut,
+        Easing::SineInOut,
+    ] {
+        assert!(easing.apply(0.0).abs() < 1e-6);
+        assert!((easing.apply(1.0) - 1.0).abs() < 1e-6);
+        assert!(easing.apply(2.0) == easing.apply(1.0));
+        assert!(easing.apply(f32::NAN) == easing.apply(0.0));
+    }
+    assert!(Easing::QuadIn.apply(0.5) < 0.5);
+    assert!(Easing::QuadOut.apply(0.5) > 0.5);
+    assert!(f32::ease(2.0, 4.0, 0.5, Easing::QuadInOut) == 3.0);
+}
+
+#[test]
+fn test_spring() {
+    let mut spring = Spring::new(0.0, 16.0, 8.0);
+    for _ in 0..600 {
+        spring.update(1.0, 1.0 / 60.0);
+        assert!(
+            spring.value <= 1.0 + 1e-3,
+            "critically damped spring overshot"
+        );
+    }
+    assert!((spring.value - 1.0).abs() < 1e-3);
 }
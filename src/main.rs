@@ -1,7 +1,8 @@
 use assets::Assets;
 use camera::Camera;
+use clock::ServerClock;
 use geng::prelude::*;
-use interpolation::Interpolated;
+use interpolation::{Easing, Interpolate, SnapshotBuffer, Spring};
 use model_draw::ModelDraw;
 
 const SPECTATOR_STR: &str = "IAMTHEATEGON";
@@ -15,7 +16,11 @@ pub struct Score {
 
 mod assets;
 mod camera;
+mod clock;
+mod encoding;
+mod map;
 mod model_draw;
+mod net_sim;
 #[cfg(not(target_arch = "wasm32"))]
 mod server;
 
@@ -26,74 +31,204 @@ type Id = i64;
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Spawn {
     pub pos: Pos,
+    pub lives: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum SharkBehavior {
+    #[default]
+    Random,
+    Hunter,
+    Cutter,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Shark {
+    /// Only the server steers sharks
+    #[serde(skip)]
+    pub behavior: SharkBehavior,
     pub destroy_timer: Option<f32>,
     pub pos: Pos,
     pub destroy: Option<vec2<i32>>,
+    pub grab_timer: Option<f32>,
+    pub grab: Option<Id>,
     pub target_pos: vec2<f32>,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum PowerUp {
+    QuickDash,
+    LongDash,
+    Immunity,
+    Repellent,
+}
+
+impl PowerUp {
+    pub const ALL: [Self; 4] = [
+        Self::QuickDash,
+        Self::LongDash,
+        Self::Immunity,
+        Self::Repellent,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::QuickDash => "quick dash",
+            Self::LongDash => "long dash",
+            Self::Immunity => "immunity",
+            Self::Repellent => "shark repellent",
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Mutator {
+    LowGravity,
+    DoubleDash,
+    SlipperyRaft,
+    ExtraSharks,
+    TinyRaft,
+}
+
+impl Mutator {
+    pub const ALL: [Self; 5] = [
+        Self::LowGravity,
+        Self::DoubleDash,
+        Self::SlipperyRaft,
+        Self::ExtraSharks,
+        Self::TinyRaft,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::LowGravity => "low gravity",
+            Self::DoubleDash => "double dash",
+            Self::SlipperyRaft => "slippery raft",
+            Self::ExtraSharks => "extra sharks",
+            Self::TinyRaft => "tiny raft",
+        }
+    }
+    pub fn apply(self, config: &mut assets::Config) {
+        let mutators = config.mutators.clone();
+        match self {
+            Self::LowGravity => config.gravity *= mutators.low_gravity_k,
+            Self::DoubleDash => config.dash_distance *= mutators.dash_k,
+            Self::SlipperyRaft => {
+                config.raft_gen.kinds.slippery += mutators.slippery_weight;
+            }
+            Self::ExtraSharks => config.shark.count += mutators.extra_sharks,
+            Self::TinyRaft => config.raft_size = mutators.tiny_raft_size,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Item {
+    pub power_up: PowerUp,
+    pub pos: vec2<f32>,
+    pub vel: vec2<f32>,
+}
+
 struct InterpolatedShark {
     pos: InterpolatedPos,
 }
 
 impl InterpolatedShark {
-    pub fn new(shark: Shark) -> Self {
+    pub fn new(time: f64, shark: Shark, max_extrapolation: f64) -> Self {
         Self {
-            pos: InterpolatedPos::new(shark.pos),
+            pos: InterpolatedPos::new(time, shark.pos, max_extrapolation),
         }
     }
-    pub fn server_update(&mut self, upd: Shark) {
-        self.pos.server_update(upd.pos);
+    pub fn server_update(&mut self, time: f64, upd: Shark) {
+        self.pos.server_update(time, upd.pos);
     }
-    fn update(&mut self, delta_time: f32) {
-        self.pos.update(delta_time);
+    fn update(&mut self, render_time: f64) {
+        self.pos.update(render_time);
     }
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Snapshot {
+    pub time: f64,
+    pub players: HashMap<Id, Pos>,
+    pub gulls: HashMap<Id, Pos>,
+    pub sharks: HashMap<Id, Shark>,
+    pub raft_load: HashMap<vec2<i32>, map::TileLoad>,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ServerMessage {
-    UpdateRaft(HashSet<vec2<i32>>),
+    UpdateRaft(HashMap<vec2<i32>, map::TileKind>),
+    Snapshot(Snapshot),
+    Detach(Id, map::Fragment),
+    FragmentSink(Id),
     YouSpawn(Spawn),
     YouDrown,
-    Pog,
+    Pog(f64),
+    YourId(Id),
     PlayerSpawn { id: Id, pos: Pos },
-    UpdatePos { id: Id, pos: Pos },
     PlayerLeft { id: Id },
-    UpdateSharks(HashMap<i64, Shark>),
+    GullLeft { id: Id },
     PlayerDrown(i64),
     Destroy(Id, vec2<i32>),
     AboutToDestroy(i64, vec2<i32>),
-    JustRestarted,
-    YouDash(vec3<f32>),
+    JustRestarted(Option<Mutator>),
+    CurrentMutator(Option<Mutator>),
+    YouDash(#[serde(with = "encoding::quantized_vec3")] vec3<f32>),
     DashRestore,
     YouStartAttack(vec2<f32>),
     StartAttack(vec2<f32>, i64),
     Dash(i64, Pos),
     YouWasPushed(vec2<f32>),
+    YouCollide(vec2<f32>),
     WasPushed(i64, Pos),
     Name(i64, String),
-    Damage(vec3<f32>),
-    UpdateGullPos { id: i64, pos: Pos },
+    Damage(#[serde(with = "encoding::quantized_vec3")] vec3<f32>),
     YouCanPoopCongratulations,
     FlyingPoop(Pos),
-    PoopOnFloor(vec2<f32>),
+    PoopOnFloor(Id, vec2<f32>),
+    PoopGone(Id),
+    YouStopSliding(#[serde(with = "encoding::quantized_vec3")] vec3<f32>),
+    YouStunned(f32),
+    Stunned(Id, f32),
     Scores(HashMap<Id, Score>),
+    Lives(HashMap<Id, usize>),
+    Pings(HashMap<Id, f32>),
+    RoundTimer(f64),
+    Sink(vec2<i32>),
+    MapName(String),
+    Bite(Id, vec2<i32>),
+    StartRepair(Id, vec2<i32>),
+    StopRepair(Id),
+    AddTile(vec2<i32>, map::TileKind),
+    ItemSpawn(Id, Item),
+    ItemGone(Id),
+    YouPickUp(PowerUp, f32),
+    AboutToGrab(Id, Id),
+    StopGrab(Id),
+    YouCanDive,
+    Brace(Id, f32),
+    BraceRestore,
+    YouGetPlank,
+    FlyingPlank(Pos),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub enum ClientMessage {
-    Pig,
+    /// Time of the last `Pog` plus how long the client waited before answering it
+    Pig(f64),
     UpdatePos(Pos),
-    Attack(vec3<f32>),
+    Attack(#[serde(with = "encoding::quantized_vec3")] vec3<f32>, f32),
     TeleportAck,
     Name(String),
     UpdateGullPos(Pos),
     Poop,
     AdminResetSecretButton,
+    Repair(vec2<i32>),
+    SnapshotRate(f32),
+    Dive,
+    DropPlank,
+    Vote(Mutator),
+    Brace,
 }
 
 #[derive(clap::Parser)]
@@ -102,6 +237,11 @@ pub struct Cli {
     pub server: Option<String>,
     #[clap(long)]
     pub connect: Option<String>,
+    /// Ask the server for fewer snapshots per second than its default
+    #[clap(long)]
+    pub snapshot_rate: Option<f32>,
+    #[clap(flatten)]
+    pub net_sim: net_sim::NetSim,
     #[clap(flatten)]
     pub geng: geng::CliArgs,
 }
@@ -133,8 +273,11 @@ impl Ctx {
 
 #[derive(Serialize, Deserialize, Debug, Copy, Clone)]
 pub struct Pos {
+    #[serde(with = "encoding::quantized_vec3")]
     pub pos: vec3<f32>,
+    #[serde(with = "encoding::quantized_angle")]
     pub rot: Angle<f32>,
+    #[serde(with = "encoding::quantized_vec3")]
     pub vel: vec3<f32>,
 }
 
@@ -142,34 +285,66 @@ impl Pos {
     pub fn transform(&self) -> mat4<f32> {
         mat4::translate(self.pos) * mat4::rotate_z(self.rot)
     }
+
+    /// Velocity kept for the whole slide, a crab standing still slides the way it faces
+    pub fn slide_start(&self, slide_speed: f32) -> vec2<f32> {
+        let dir = self.vel.xy().normalize_or_zero();
+        let dir = if dir == vec2::ZERO {
+            vec2(1.0, 0.0).rotate(self.rot)
+        } else {
+            dir
+        };
+        dir * slide_speed
+    }
+
+    /// Same step on client and server so they agree until `YouStopSliding`
+    pub fn slide(&mut self, vel: vec2<f32>, delta_time: f32) {
+        self.vel = vel.extend(0.0);
+        self.pos += self.vel * delta_time;
+    }
 }
 
+/// Remote entity rendered at `render_time`, a bit behind the latest snapshot
 pub struct InterpolatedPos {
-    pub pos: Interpolated<vec3<f32>>,
-    pub rot: Interpolated<Angle<f32>>,
+    pos: SnapshotBuffer<vec3<f32>>,
+    rot: SnapshotBuffer<Angle<f32>>,
+    current: Pos,
 }
 
 impl InterpolatedPos {
-    pub fn new(pos: Pos) -> Self {
-        Self {
-            pos: Interpolated::new(pos.pos, pos.vel),
-            rot: Interpolated::new(pos.rot, Angle::ZERO),
-        }
+    pub fn new(time: f64, pos: Pos, max_extrapolation: f64) -> Self {
+        let mut result = Self {
+            pos: SnapshotBuffer::new(max_extrapolation),
+            rot: SnapshotBuffer::new(max_extrapolation),
+            current: pos,
+        };
+        result.server_update(time, pos);
+        result
     }
-    pub fn server_update(&mut self, pos: Pos) {
-        self.pos.server_update(pos.pos, pos.vel);
-        self.rot.server_update(pos.rot, Angle::ZERO);
+    pub fn server_update(&mut self, time: f64, pos: Pos) {
+        self.pos.push(time, pos.pos, pos.vel);
+        self.rot.push_angle(time, pos.rot);
     }
-    pub fn update(&mut self, delta_time: f32) {
-        self.pos.update(delta_time);
-        self.rot.update(delta_time);
+    /// Forget the history so the entity doesn't slide through the jump
+    pub fn teleport(&mut self, time: f64, pos: Pos) {
+        self.pos.clear();
+        self.rot.clear();
+        self.server_update(time, pos);
+        self.current = pos;
     }
-    fn get(&self) -> Pos {
-        Pos {
-            pos: self.pos.get(),
-            rot: self.rot.get(),
-            vel: self.pos.get_derivative(),
+    pub fn update(&mut self, render_time: f64) {
+        self.pos.prune(render_time);
+        self.rot.prune(render_time);
+        if let Some((pos, vel)) = self.pos.get(render_time) {
+            self.current.pos = pos;
+            self.current.vel = vel;
         }
+        if let Some((rot, _)) = self.rot.get(render_time) {
+            self.current.rot = rot;
+        }
+    }
+    fn get(&self) -> Pos {
+        self.current
     }
 }
 
@@ -199,10 +374,36 @@ impl Vfx {
     }
 }
 
+const VOTE_KEYS: [geng::Key; 5] = [
+    geng::Key::Digit1,
+    geng::Key::Digit2,
+    geng::Key::Digit3,
+    geng::Key::Digit4,
+    geng::Key::Digit5,
+];
+
 pub struct Game {
     hide_names: bool,
+    show_net_stats: bool,
+    pings: HashMap<Id, f32>,
+    messages_received: usize,
+    messages_timer: f32,
+    messages_per_second: f32,
+    config: assets::Config,
+    mutator: Option<Mutator>,
+    my_vote: Option<Mutator>,
     can_poop: bool,
-    floor_poop: Vec<vec2<f32>>,
+    can_dive: bool,
+    dive_timer: f32,
+    has_plank: bool,
+    flying_planks: Vec<Pos>,
+    floor_poop: HashMap<Id, vec2<f32>>,
+    my_slide: Option<vec2<f32>>,
+    my_stun: f32,
+    stunned: HashMap<Id, f32>,
+    can_brace: bool,
+    my_brace: f32,
+    braced: HashMap<Id, f32>,
     flying_poops: Vec<Pos>,
     names: HashMap<Id, String>,
     name: String,
@@ -210,32 +411,69 @@ pub struct Game {
     attacks: HashSet<Id>,
     attacking: bool,
     can_dash: bool,
+    dash_charge: Option<f32>,
     shark_attacks: HashMap<Id, vec2<i32>>,
-    con: geng::net::client::Connection<ServerMessage, ClientMessage>,
+    shark_grabs: HashMap<Id, Id>,
+    con: net_sim::Connection<ServerMessage, ClientMessage>,
     ctx: Ctx,
     me: Option<Pos>,
     me_gull: Pos,
     camera: Camera,
+    spectator_zoom: Spring<f32>,
     framebuffer_size: vec2<f32>,
     time: f32,
     wave_dir: vec2<f32>,
+    my_id: Option<Id>,
     others: HashMap<Id, OtherPlayer>,
     other_gulls: HashMap<Id, OtherPlayer>,
-    raft: HashSet<vec2<i32>>,
+    raft: HashMap<vec2<i32>, map::TileKind>,
+    raft_load: HashMap<vec2<i32>, map::TileLoad>,
+    sinking_tiles: Vec<(vec2<i32>, map::TileKind, f32)>,
+    fragments: HashMap<Id, (map::Fragment, Option<f32>)>,
     sharks: HashMap<Id, InterpolatedShark>,
     vfx: Vec<Vfx>,
     scores: HashMap<Id, Score>,
+    lives: HashMap<Id, usize>,
+    my_lives: usize,
+    respawn_timer: Option<f32>,
+    clock: ServerClock,
+    pig_timer: Option<f32>,
+    last_pog: f64,
+    upload_timer: f32,
+    sudden_death_at: Option<f64>,
+    map_name: String,
+    map_name_timer: f32,
+    repairs: HashMap<Id, (vec2<i32>, f32)>,
+    items: HashMap<Id, Item>,
+    power_ups: HashMap<PowerUp, f32>,
 }
 
 impl Game {
     pub fn new(
         ctx: &Ctx,
-        con: geng::net::client::Connection<ServerMessage, ClientMessage>,
+        mut con: net_sim::Connection<ServerMessage, ClientMessage>,
+        snapshot_rate: Option<f32>,
     ) -> Self {
+        if let Some(rate) = snapshot_rate {
+            con.send(ClientMessage::SnapshotRate(rate));
+        }
         Self {
+            config: ctx.assets.config.clone(),
+            mutator: None,
+            my_vote: None,
             floor_poop: default(),
+            my_slide: None,
+            my_stun: 0.0,
+            stunned: default(),
+            can_brace: true,
+            my_brace: 0.0,
+            braced: default(),
             flying_poops: default(),
             can_poop: true,
+            can_dive: true,
+            dive_timer: 0.0,
+            has_plank: true,
+            flying_planks: default(),
             other_gulls: default(),
             names: default(),
             name: "".to_owned(),
@@ -255,11 +493,23 @@ impl Game {
             attacks: default(),
             attacking: false,
             hide_names: false,
+            show_net_stats: false,
+            pings: default(),
+            messages_received: 0,
+            messages_timer: 0.0,
+            messages_per_second: 0.0,
             can_dash: true,
+            dash_charge: None,
             shark_attacks: default(),
+            shark_grabs: default(),
             con,
             ctx: ctx.clone(),
             me: None,
+            spectator_zoom: Spring::new(
+                ctx.assets.config.camera.distance,
+                ctx.assets.config.spectator.zoom_stiffness,
+                ctx.assets.config.spectator.zoom_damping,
+            ),
             camera: Camera {
                 pos: vec3::ZERO,
                 fov: Angle::from_degrees(ctx.assets.config.camera.fov),
@@ -270,11 +520,28 @@ impl Game {
             framebuffer_size: vec2::splat(1.0),
             time: 0.0,
             wave_dir: ctx.assets.config.wave.dir.normalize_or_zero(),
+            my_id: None,
             others: default(),
             raft: default(),
+            raft_load: default(),
+            sinking_tiles: default(),
+            fragments: default(),
             sharks: default(),
             vfx: default(),
             scores: default(),
+            lives: default(),
+            my_lives: 0,
+            respawn_timer: None,
+            clock: ServerClock::new(),
+            pig_timer: None,
+            last_pog: 0.0,
+            upload_timer: 0.0,
+            sudden_death_at: None,
+            map_name: String::new(),
+            map_name_timer: 0.0,
+            repairs: default(),
+            items: default(),
+            power_ups: default(),
         }
     }
     pub async fn run(mut self) {
@@ -301,6 +568,30 @@ impl Game {
                 geng::Event::KeyPress { key: geng::Key::H } => {
                     self.hide_names = !self.hide_names;
                 }
+                geng::Event::KeyPress { key: geng::Key::N } => {
+                    self.show_net_stats = !self.show_net_stats;
+                }
+                geng::Event::KeyPress { key }
+                    if self.me.is_none()
+                        && self.name != SPECTATOR_STR
+                        && VOTE_KEYS.contains(&key) =>
+                {
+                    let index = VOTE_KEYS.iter().position(|&k| k == key).unwrap();
+                    let mutator = Mutator::ALL[index];
+                    self.my_vote = Some(mutator);
+                    self.con.send(ClientMessage::Vote(mutator));
+                }
+                geng::Event::KeyPress { key: geng::Key::E } => {
+                    if let Some(me) = &self.me {
+                        let tile_size = self.ctx.assets.config.tile_size;
+                        let target = me.pos.xy() + vec2(tile_size, 0.0).rotate(me.rot);
+                        let tile = target.map(|x| (x / tile_size).round() as i32);
+                        self.con.send(ClientMessage::Repair(tile));
+                    } else if self.has_plank && self.name != SPECTATOR_STR {
+                        self.has_plank = false;
+                        self.con.send(ClientMessage::DropPlank);
+                    }
+                }
 
                 geng::Event::KeyPress { key: geng::Key::R } if self.name == SPECTATOR_STR => {
                     self.con.send(ClientMessage::AdminResetSecretButton);
@@ -316,20 +607,41 @@ impl Game {
                     self.con.send(ClientMessage::Poop);
                 }
                 geng::Event::MousePress {
+                    button: geng::MouseButton::Right,
+                } if self.me.is_none() && self.can_dive && self.name != SPECTATOR_STR => {
+                    self.can_dive = false;
+                    self.dive_timer = self.ctx.assets.config.gull.dive_time;
+                    self.con.send(ClientMessage::Dive);
+                }
+                geng::Event::MousePress {
+                    button: geng::MouseButton::Right,
+                } if self.me.is_some() && self.can_brace && self.my_stun <= 0.0 => {
+                    self.can_brace = false;
+                    self.my_brace = self.config.brace.time;
+                    self.con.send(ClientMessage::Brace);
+                }
+                geng::Event::MousePress {
+                    button: geng::MouseButton::Left,
+                } if self.me.is_some() && self.can_dash && self.my_stun <= 0.0 => {
+                    self.dash_charge = Some(0.0);
+                }
+                geng::Event::MouseRelease {
                     button: geng::MouseButton::Left,
-                } if self.can_dash => {
-                    if let Some(pos) = self.ctx.geng.window().cursor_position() {
-                        let ray = self
-                            .camera
-                            .pixel_ray(self.framebuffer_size, pos.map(|x| x as f32));
-                        if ray.dir.z < -1e-5 {
-                            let t = -ray.from.z / ray.dir.z;
-                            let ground_pos = ray.from + ray.dir * t;
-
-                            if let Some(_me) = &self.me {
-                                self.con.send(ClientMessage::Attack(ground_pos));
-                                self.attacking = true;
-                                self.can_dash = false;
+                } => {
+                    if let Some(charge) = self.dash_charge.take() {
+                        if let Some(pos) = self.ctx.geng.window().cursor_position() {
+                            let ray = self
+                                .camera
+                                .pixel_ray(self.framebuffer_size, pos.map(|x| x as f32));
+                            if ray.dir.z < -1e-5 && self.can_dash && self.my_stun <= 0.0 {
+                                let t = -ray.from.z / ray.dir.z;
+                                let ground_pos = ray.from + ray.dir * t;
+
+                                if let Some(_me) = &self.me {
+                                    self.con.send(ClientMessage::Attack(ground_pos, charge));
+                                    self.attacking = true;
+                                    self.can_dash = false;
+                                }
                             }
                         }
                     }
@@ -345,20 +657,131 @@ impl Game {
                 _ => {}
             }
 
-            let new_messages: Vec<_> = self.con.new_messages().collect();
+            let new_messages = self.con.new_messages();
+            self.messages_received += new_messages.len();
             for message in new_messages {
-                self.handle_server(message.unwrap());
+                self.handle_server(message);
             }
         }
     }
 
+    /// Server time remote entities are drawn at, so there is usually a snapshot on both sides
+    fn render_time(&self) -> f64 {
+        self.clock.now() - self.ctx.assets.config.snapshot.render_delay
+    }
+
+    fn set_mutator(&mut self, mutator: Option<Mutator>) {
+        self.config = self.ctx.assets.config.clone();
+        if let Some(mutator) = mutator {
+            mutator.apply(&mut self.config);
+        }
+        self.mutator = mutator;
+    }
+
+    fn sink_tile(&mut self, tile: vec2<i32>) {
+        self.raft_load.remove(&tile);
+        if let Some(kind) = self.raft.remove(&tile) {
+            self.sinking_tiles.push((tile, kind, 0.0));
+        }
+        self.ctx.assets.sfx.destroy.play();
+        self.vfx.push(Vfx::new(
+            &self.ctx.assets.destroy,
+            tile.map(|x| x as f32 * self.ctx.assets.config.tile_size)
+                .extend(0.0),
+        ));
+    }
+
     fn handle_server(&mut self, message: ServerMessage) {
         match message {
             ServerMessage::Scores(scores) => {
                 self.scores = scores;
             }
-            ServerMessage::PoopOnFloor(pos) => {
-                self.floor_poop.push(pos);
+            ServerMessage::Lives(lives) => {
+                self.lives = lives;
+            }
+            ServerMessage::Pings(pings) => {
+                self.pings = pings;
+            }
+            ServerMessage::MapName(name) => {
+                self.map_name = name;
+                self.map_name_timer = 3.0;
+            }
+            ServerMessage::RoundTimer(time) => {
+                self.sudden_death_at = Some(time);
+            }
+            ServerMessage::Sink(tile) => {
+                self.sink_tile(tile);
+            }
+            ServerMessage::Snapshot(snapshot) => {
+                let time = snapshot.time;
+                let max_extrapolation = self.ctx.assets.config.snapshot.max_extrapolation;
+                for (id, pos) in snapshot.players {
+                    if let Some(other) = self.others.get_mut(&id) {
+                        other.pos.server_update(time, pos);
+                    }
+                }
+                for (id, pos) in snapshot.gulls {
+                    match self.other_gulls.entry(id) {
+                        std::collections::hash_map::Entry::Occupied(mut other) => {
+                            other.get_mut().pos.server_update(time, pos);
+                        }
+                        std::collections::hash_map::Entry::Vacant(e) => {
+                            e.insert(OtherPlayer {
+                                pos: InterpolatedPos::new(time, pos, max_extrapolation),
+                            });
+                        }
+                    }
+                }
+                self.sharks.retain(|id, _| snapshot.sharks.contains_key(id));
+                for (id, shark) in snapshot.sharks {
+                    if let Some(cur) = self.sharks.get_mut(&id) {
+                        cur.server_update(time, shark);
+                    } else {
+                        self.sharks
+                            .insert(id, InterpolatedShark::new(time, shark, max_extrapolation));
+                    }
+                }
+                self.raft_load = snapshot.raft_load;
+            }
+            ServerMessage::Detach(id, fragment) => {
+                for tile in fragment.tiles.keys() {
+                    self.raft.remove(tile);
+                    self.raft_load.remove(tile);
+                }
+                self.fragments.insert(id, (fragment, None));
+            }
+            ServerMessage::FragmentSink(id) => {
+                if let Some((_, sinking)) = self.fragments.get_mut(&id) {
+                    *sinking = Some(0.0);
+                    self.ctx.assets.sfx.destroy.play();
+                }
+            }
+            ServerMessage::PoopGone(id) => {
+                self.floor_poop.remove(&id);
+            }
+            ServerMessage::YouStopSliding(pos) => {
+                self.con.send(ClientMessage::TeleportAck);
+                self.my_slide = None;
+                if let Some(me) = &mut self.me {
+                    me.pos = pos;
+                }
+            }
+            ServerMessage::YouStunned(time) => {
+                self.my_stun = time;
+                self.ctx.assets.sfx.wet_fart.play();
+            }
+            ServerMessage::Brace(id, time) => {
+                self.braced.insert(id, time);
+            }
+            ServerMessage::BraceRestore => {
+                self.can_brace = true;
+            }
+            ServerMessage::Stunned(id, time) => {
+                self.stunned.insert(id, time);
+                self.ctx.assets.sfx.wet_fart.play();
+            }
+            ServerMessage::PoopOnFloor(id, pos) => {
+                self.floor_poop.insert(id, pos);
                 self.ctx.assets.sfx.wet_fart.play();
             }
             ServerMessage::FlyingPoop(pos) => {
@@ -368,16 +791,15 @@ impl Game {
             ServerMessage::YouCanPoopCongratulations => {
                 self.can_poop = true;
             }
-            ServerMessage::UpdateGullPos { id, pos } => match self.other_gulls.entry(id) {
-                std::collections::hash_map::Entry::Occupied(mut other) => {
-                    other.get_mut().pos.server_update(pos);
-                }
-                std::collections::hash_map::Entry::Vacant(e) => {
-                    e.insert(OtherPlayer {
-                        pos: InterpolatedPos::new(pos),
-                    });
-                }
-            },
+            ServerMessage::YouCanDive => {
+                self.can_dive = true;
+            }
+            ServerMessage::YouGetPlank => {
+                self.has_plank = true;
+            }
+            ServerMessage::FlyingPlank(pos) => {
+                self.flying_planks.push(pos);
+            }
             ServerMessage::Damage(pos) => {
                 self.vfx
                     .push(Vfx::new(&self.ctx.assets.damage, pos + vec3(0.0, 0.0, 1.2)));
@@ -387,10 +809,10 @@ impl Game {
             }
             ServerMessage::WasPushed(id, new_pos) => {
                 self.ctx.assets.sfx.bonk.play();
+                let render_time = self.render_time();
                 if let Some(other) = self.others.get_mut(&id) {
                     let old_pos = other.pos.get().pos;
-                    other.pos.server_update(new_pos);
-                    other.pos.update(1.0);
+                    other.pos.teleport(render_time, new_pos);
 
                     self.vfx.push(Vfx::new_rot(
                         &self.ctx.assets.push,
@@ -410,15 +832,20 @@ impl Game {
                         .push(Vfx::new_rot(&self.ctx.assets.push, me.pos, delta.arg()));
                 }
             }
+            ServerMessage::YouCollide(delta) => {
+                if let Some(me) = &mut self.me {
+                    me.pos += delta.extend(0.0);
+                }
+            }
             ServerMessage::StartAttack(_new_pos, id) => {
                 self.attacks.insert(id);
             }
             ServerMessage::Dash(id, new_pos) => {
                 self.attacks.remove(&id);
+                let render_time = self.render_time();
                 if let Some(other) = self.others.get_mut(&id) {
                     let old_pos = other.pos.get().pos;
-                    other.pos.server_update(new_pos);
-                    other.pos.update(1.0);
+                    other.pos.teleport(render_time, new_pos);
                     let new_pos = new_pos.pos;
                     self.ctx.assets.sfx.dash.play();
                     self.vfx.push(Vfx::new_rot(
@@ -447,21 +874,71 @@ impl Game {
                     ));
                 }
             }
-            ServerMessage::JustRestarted => {
+            ServerMessage::JustRestarted(mutator) => {
+                self.set_mutator(mutator);
+                self.my_vote = None;
+                self.shark_grabs.clear();
+                self.repairs.clear();
+                self.items.clear();
+                self.power_ups.clear();
                 self.shark_attacks.clear();
                 self.attacks.clear();
                 self.floor_poop.clear();
+                self.flying_planks.clear();
+                self.stunned.clear();
+                self.my_stun = 0.0;
+                self.braced.clear();
+                self.my_brace = 0.0;
+            }
+            ServerMessage::CurrentMutator(mutator) => {
+                self.set_mutator(mutator);
             }
             ServerMessage::Destroy(shark, tile) => {
                 self.shark_attacks.remove(&shark);
-                self.raft.remove(&tile);
+                self.sink_tile(tile);
+            }
+            ServerMessage::ItemSpawn(id, item) => {
+                self.items.insert(id, item);
+            }
+            ServerMessage::ItemGone(id) => {
+                self.items.remove(&id);
+            }
+            ServerMessage::YouPickUp(power_up, time) => {
+                self.power_ups.insert(power_up, time);
+            }
+            ServerMessage::StartRepair(id, tile) => {
+                self.repairs.insert(id, (tile, 0.0));
+            }
+            ServerMessage::StopRepair(id) => {
+                self.repairs.remove(&id);
+            }
+            ServerMessage::AddTile(tile, kind) => {
+                self.raft.insert(tile, kind);
+                self.vfx.push(Vfx::new(
+                    &self.ctx.assets.splash,
+                    tile.map(|x| x as f32 * self.ctx.assets.config.tile_size)
+                        .extend(0.0),
+                ));
+            }
+            ServerMessage::Bite(shark, tile) => {
+                self.shark_attacks.remove(&shark);
+                if let Some(kind) = self.raft.get_mut(&tile) {
+                    *kind = kind.bitten().unwrap_or(*kind);
+                }
                 self.ctx.assets.sfx.destroy.play();
                 self.vfx.push(Vfx::new(
-                    &self.ctx.assets.destroy,
+                    &self.ctx.assets.damage,
                     tile.map(|x| x as f32 * self.ctx.assets.config.tile_size)
                         .extend(0.0),
                 ));
             }
+            ServerMessage::AboutToGrab(shark, player) => {
+                self.shark_grabs.insert(shark, player);
+                self.ctx.assets.sfx.eating.play();
+            }
+            ServerMessage::StopGrab(shark) => {
+                self.shark_grabs.remove(&shark);
+            }
             ServerMessage::AboutToDestroy(shark, tile) => {
                 self.shark_attacks.insert(shark, tile);
                 self.ctx.assets.sfx.eating.play();
@@ -476,6 +953,11 @@ impl Game {
                 }
             }
             ServerMessage::YouDrown => {
+                self.power_ups.clear();
+                self.my_lives = self.my_lives.saturating_sub(1);
+                if self.my_lives > 0 {
+                    self.respawn_timer = Some(self.ctx.assets.config.respawn.delay);
+                }
                 if let Some(me) = self.me.take() {
                     self.me_gull.pos = me.pos.xy().extend(self.me_gull.pos.z);
                     self.vfx
@@ -485,45 +967,43 @@ impl Game {
             }
             ServerMessage::YouSpawn(spawn) => {
                 self.me = Some(spawn.pos);
+                self.my_slide = None;
+                self.my_lives = spawn.lives;
+                self.respawn_timer = None;
                 self.attacking = false;
                 self.can_dash = true;
+                self.dash_charge = None;
             }
             ServerMessage::PlayerSpawn { id, pos } => {
+                let max_extrapolation = self.ctx.assets.config.snapshot.max_extrapolation;
                 self.others.insert(
                     id,
                     OtherPlayer {
-                        pos: InterpolatedPos::new(pos),
+                        pos: InterpolatedPos::new(self.render_time(), pos, max_extrapolation),
                     },
                 );
                 self.attacks.remove(&id);
             }
-            ServerMessage::UpdatePos { id, pos } => {
-                self.others.get_mut(&id).unwrap().pos.server_update(pos);
-            }
             ServerMessage::PlayerLeft { id } => {
                 self.others.remove(&id);
                 self.other_gulls.remove(&id);
             }
-            ServerMessage::Pog => {
-                self.con.send(ClientMessage::Pig);
-                if let Some(me) = &self.me {
-                    self.con.send(ClientMessage::UpdatePos(me.clone()));
-                } else if self.name != SPECTATOR_STR {
-                    self.con.send(ClientMessage::UpdateGullPos(self.me_gull));
-                }
+            ServerMessage::YourId(id) => {
+                self.my_id = Some(id);
+            }
+            ServerMessage::GullLeft { id } => {
+                self.other_gulls.remove(&id);
+            }
+            ServerMessage::Pog(server_time) => {
+                self.clock.pog(server_time);
+                self.last_pog = server_time;
+                self.pig_timer = Some(self.ctx.assets.config.snapshot.ping_interval);
             }
             ServerMessage::UpdateRaft(raft) => {
                 self.raft = raft;
-            }
-            ServerMessage::UpdateSharks(sharks) => {
-                self.sharks.retain(|id, _| sharks.contains_key(id));
-                for (id, shark) in sharks {
-                    if let Some(cur) = self.sharks.get_mut(&id) {
-                        cur.server_update(shark);
-                    } else {
-                        self.sharks.insert(id, InterpolatedShark::new(shark));
-                    }
-                }
+                self.raft_load.clear();
+                self.sinking_tiles.clear();
+                self.fragments.clear();
             }
         }
     }
@@ -531,6 +1011,9 @@ impl Game {
     fn update(&mut self, delta_time: time::Duration) {
         let delta_time = delta_time.as_secs_f64() as f32;
         self.time += delta_time;
+        if let Some(charge) = &mut self.dash_charge {
+            *charge += delta_time;
+        }
 
         let target_pos = if let Some(me) = &mut self.me {
             let mut mov = vec2::<f32>::ZERO;
@@ -560,34 +1043,44 @@ impl Game {
                 .rotate(self.camera.rot)
                 .rotate(-me.rot);
 
+            // Fragments carry whoever stands on them, sliding or not
+            let tile_size = self.ctx.assets.config.tile_size;
+            if let Some((fragment, _)) = self.fragments.values().find(|(fragment, sinking)| {
+                sinking.is_none() && fragment.contains(me.pos.xy(), tile_size)
+            }) {
+                me.pos += fragment.vel.extend(0.0) * delta_time;
+            }
+
+            let tile = me
+                .pos
+                .xy()
+                .map(|x| (x / self.ctx.assets.config.tile_size).round() as i32);
             let on_poop = self
                 .floor_poop
-                .iter()
+                .values()
                 .copied()
-                .any(|poop| (poop - me.pos.xy()).len() < 3.0);
+                .any(|poop| (poop - me.pos.xy()).len() < 3.0)
+                || self.raft.get(&tile) == Some(&map::TileKind::Slippery);
             if on_poop {
-                me.vel = me.vel.normalize_or_zero() * self.ctx.assets.config.slide_speed;
-            } else if self.attacking {
-                me.vel = vec3::ZERO;
+                let slide_speed = self.ctx.assets.config.slide_speed;
+                let vel = *self
+                    .my_slide
+                    .get_or_insert_with(|| me.slide_start(slide_speed));
+                me.slide(vel, delta_time);
             } else {
-                me.vel = (mov
-                    * vec2(
-                        self.ctx.assets.config.forward_speed,
-                        self.ctx.assets.config.side_speed,
-                    ))
-                .rotate(me.rot)
-                .extend(0.0);
-            }
-            me.pos += me.vel * delta_time;
-
-            for other in self.others.values() {
-                let delta = me.pos.xy() - other.pos.get().pos.xy();
-                if delta.len() < 2.0 {
-                    let pen = 2.0 - delta.len();
-                    me.pos += (delta.normalize_or_zero() * pen)
-                        .clamp_len(..=self.ctx.assets.config.collide_speed * delta_time)
-                        .extend(0.0);
+                self.my_slide = None;
+                if self.attacking || self.my_stun > 0.0 {
+                    me.vel = vec3::ZERO;
+                } else {
+                    me.vel = (mov
+                        * vec2(
+                            self.ctx.assets.config.forward_speed,
+                            self.ctx.assets.config.side_speed,
+                        ))
+                    .rotate(me.rot)
+                    .extend(0.0);
                 }
+                me.pos += me.vel * delta_time;
             }
 
             if let Some(pos) = self.ctx.geng.window().cursor_position() {
@@ -631,6 +1124,11 @@ impl Game {
                 .rotate(self.me_gull.rot)
                 .extend(0.0);
             self.me_gull.pos += self.me_gull.vel * delta_time;
+            self.dive_timer = (self.dive_timer - delta_time).max(0.0);
+            let dive = (self.dive_timer / self.ctx.assets.config.gull.dive_time
+                * std::f32::consts::PI)
+                .sin();
+            self.me_gull.pos.z = self.ctx.assets.config.seagull_height * (1.0 - 0.8 * dive);
             self.me_gull.pos
         } else {
             // SPECTATOR MODE
@@ -639,9 +1137,7 @@ impl Game {
             } else {
                 self.ctx.assets.config.camera.distance
             };
-            let delta = target_distance - self.camera.distance;
-            self.camera.distance +=
-                (delta * self.ctx.assets.config.camera.speed * delta_time).clamp_abs(delta.abs());
+            self.camera.distance = self.spectator_zoom.update(target_distance, delta_time);
 
             self.camera.rot +=
                 Angle::from_degrees(self.ctx.assets.config.spectator.rotate_speed) * delta_time;
@@ -661,26 +1157,96 @@ impl Game {
         self.camera.pos +=
             (delta * self.ctx.assets.config.camera.speed * delta_time).clamp_len(..=delta.len());
 
+        let render_time = self.render_time();
         for other in self.others.values_mut() {
-            other.pos.update(delta_time);
+            other.pos.update(render_time);
         }
         for shark in self.sharks.values_mut() {
-            shark.update(delta_time);
+            shark.update(render_time);
         }
         for other in self.other_gulls.values_mut() {
-            other.pos.update(delta_time);
+            other.pos.update(render_time);
         }
 
+        if let Some(time) = &mut self.respawn_timer {
+            *time -= delta_time;
+        }
+        self.map_name_timer -= delta_time;
+        if let Some(time) = &mut self.pig_timer {
+            *time -= delta_time;
+            if *time <= 0.0 {
+                let waited = self.ctx.assets.config.snapshot.ping_interval - *time;
+                self.pig_timer = None;
+                self.con
+                    .send(ClientMessage::Pig(self.last_pog + waited as f64));
+                self.clock.pig_sent();
+            }
+        }
+        self.upload_timer += delta_time;
+        if self.upload_timer >= 1.0 / self.ctx.assets.config.snapshot.upload_rate {
+            self.upload_timer = 0.0;
+            if let Some(me) = &self.me {
+                self.con.send(ClientMessage::UpdatePos(*me));
+            } else if self.name != SPECTATOR_STR {
+                self.con.send(ClientMessage::UpdateGullPos(self.me_gull));
+            }
+        }
+        self.messages_timer += delta_time;
+        if self.messages_timer > 1.0 {
+            self.messages_per_second = self.messages_received as f32 / self.messages_timer;
+            self.messages_received = 0;
+            self.messages_timer = 0.0;
+        }
+        for (_, time) in self.repairs.values_mut() {
+            *time += delta_time;
+        }
+        self.my_stun -= delta_time;
+        for time in self.stunned.values_mut() {
+            *time -= delta_time;
+        }
+        self.stunned.retain(|_, time| *time > 0.0);
+        self.my_brace -= delta_time;
+        for time in self.braced.values_mut() {
+            *time -= delta_time;
+        }
+        self.braced.retain(|_, time| *time > 0.0);
+        for item in self.items.values_mut() {
+            item.pos += item.vel * delta_time;
+        }
+        for time in self.power_ups.values_mut() {
+            *time -= delta_time;
+        }
+        self.power_ups.retain(|_, time| *time > 0.0);
+
         for vfx in &mut self.vfx {
             vfx.t += delta_time;
         }
         self.vfx.retain(|vfx| vfx.t < vfx.max_t);
 
+        for (_, _, time) in &mut self.sinking_tiles {
+            *time += delta_time;
+        }
+        let sink_time = self.ctx.assets.config.raft_physics.sink_time;
+        self.sinking_tiles.retain(|&(_, _, time)| time < sink_time);
+        for (fragment, sinking) in self.fragments.values_mut() {
+            fragment.offset += fragment.vel * delta_time;
+            if let Some(time) = sinking {
+                *time += delta_time;
+            }
+        }
+        self.fragments
+            .retain(|_, (_, sinking)| sinking.map_or(true, |time| time < sink_time));
+
         for poop in &mut self.flying_poops {
-            poop.vel.z -= self.ctx.assets.config.gravity * delta_time;
+            poop.vel.z -= self.config.gravity * delta_time;
             poop.pos += poop.vel * delta_time;
         }
         self.flying_poops.retain(|poop| poop.pos.z > 0.0);
+        for plank in &mut self.flying_planks {
+            plank.vel.z -= self.config.gravity * delta_time;
+            plank.pos += plank.vel * delta_time;
+        }
+        self.flying_planks.retain(|plank| plank.pos.z > 0.0);
     }
 
     fn draw_crab(
@@ -699,24 +1265,40 @@ impl Game {
             .get(&name)
             .unwrap_or(&self.ctx.assets.crab);
         let winner = self.others.len() + self.me.is_some() as usize == 1;
+        let tile = pos
+            .pos
+            .xy()
+            .map(|x| (x / self.ctx.assets.config.tile_size).round() as i32);
         let on_poop = self
             .floor_poop
-            .iter()
+            .values()
             .copied()
-            .any(|poop| (poop - pos.pos.xy()).len() < 3.0);
+            .any(|poop| (poop - pos.pos.xy()).len() < 3.0)
+            || self.raft.get(&tile) == Some(&map::TileKind::Slippery);
         let mut transform = pos.transform()
             * mat4::translate(
                 vec3::UNIT_Z
-                    * (/*self.height_at(pos.pos.xy()) +*/self.ctx.assets.config.crab_animation.z),
+                    * (self.height_at(pos.pos.xy()) + self.ctx.assets.config.crab_animation.z),
             );
+        let braced = match id {
+            Some(id) => self.braced.contains_key(&id),
+            None => self.my_brace > 0.0,
+        };
         if attacking {
             transform *= mat4::rotate_y(-Angle::from_degrees(40.0));
+        } else if braced {
+            transform *= mat4::scale(vec3(1.2, 1.2, 0.6));
         } else if winner {
             transform *= mat4::translate(vec3(0.0, 0.0, (self.time * 10.0).sin().abs() * 0.5));
         }
+        let color = if braced {
+            self.config.brace.color
+        } else {
+            Rgba::WHITE
+        };
         self.ctx
             .model_draw
-            .draw(framebuffer, &self.camera, &crab.body, transform);
+            .draw_colored(framebuffer, &self.camera, &crab.body, transform, color);
         if !on_poop {
             transform *= mat4::rotate_z(Angle::from_degrees(
                 (self.time * self.ctx.assets.config.crab_animation.legs_freq).sin()
@@ -727,6 +1309,18 @@ impl Game {
         self.ctx
             .model_draw
             .draw(framebuffer, &self.camera, &crab.legs, transform);
+        let stunned = match id {
+            Some(id) => self.stunned.contains_key(&id),
+            None => self.my_stun > 0.0,
+        };
+        if stunned {
+            self.ctx.model_draw.draw(
+                framebuffer,
+                &self.camera,
+                &self.ctx.assets.poop,
+                pos.transform() * mat4::translate(vec3(0.0, 0.0, 1.2)),
+            );
+        }
     }
 
     fn draw_gull(&self, framebuffer: &mut ugli::Framebuffer, pos: Pos) {
@@ -757,6 +1351,20 @@ impl Game {
                     &self.ctx.assets.dash_arrow,
                     me.transform() * mat4::translate(vec3(1.0, 0.0, 0.55)),
                 );
+                if let Some(charge) = self.dash_charge {
+                    let config = &self.config.dash_charge;
+                    let k = config.distance_k(charge);
+                    let t = charge / config.max_time;
+                    self.ctx.model_draw.draw_colored(
+                        framebuffer,
+                        &self.camera,
+                        &self.ctx.assets.dash_arrow,
+                        me.transform()
+                            * mat4::translate(vec3(1.0, 0.0, 0.5))
+                            * mat4::scale(vec3(k, 1.0 + 0.2 * k, 1.0)),
+                        Interpolate::ease(Rgba::WHITE, config.color, t, config.easing),
+                    );
+                }
             }
         } else if self.name != SPECTATOR_STR {
             self.draw_gull(framebuffer, self.me_gull);
@@ -768,6 +1376,16 @@ impl Game {
                     mat4::translate(self.me_gull.pos.xy().extend(0.55)),
                 );
             }
+            if self.has_plank {
+                self.ctx.model_draw.draw(
+                    framebuffer,
+                    &self.camera,
+                    &self.ctx.assets.raft_tile,
+                    self.me_gull.transform()
+                        * mat4::translate(vec3(0.0, 0.0, -0.5))
+                        * mat4::scale_uniform(0.3),
+                );
+            }
         }
         for (&id, other) in &self.others {
             self.draw_crab(
@@ -782,6 +1400,14 @@ impl Game {
                 self.draw_gull(framebuffer, other.pos.get());
             }
         }
+        for plank in &self.flying_planks {
+            self.ctx.model_draw.draw(
+                framebuffer,
+                &self.camera,
+                &self.ctx.assets.raft_tile,
+                plank.transform() * mat4::scale_uniform(0.3),
+            );
+        }
         for poop in &self.flying_poops {
             self.ctx.model_draw.draw(
                 framebuffer,
@@ -792,17 +1418,26 @@ impl Game {
         }
 
         for (id, shark) in &self.sharks {
-            if let Some(tile) = self.shark_attacks.get(id) {
+            let target = self
+                .shark_attacks
+                .get(id)
+                .map(|tile| tile.map(|x| x as f32 * self.ctx.assets.config.tile_size))
+                .or_else(|| {
+                    let player = self.shark_grabs.get(id)?;
+                    match self.others.get(player) {
+                        Some(other) => Some(other.pos.get().pos.xy()),
+                        None if self.my_id == Some(*player) => self.me.map(|me| me.pos.xy()),
+                        None => None,
+                    }
+                });
+            if let Some(target) = target {
                 let pos = shark.pos.get().pos;
                 self.ctx.model_draw.draw(
                     framebuffer,
                     &self.camera,
                     &self.ctx.assets.shark,
                     mat4::translate(pos + vec3(0.0, 0.0, 2.5))
-                        * mat4::rotate_z(
-                            (tile.map(|x| x as f32 * self.ctx.assets.config.tile_size) - pos.xy())
-                                .arg(),
-                        )
+                        * mat4::rotate_z((target - pos.xy()).arg())
                         * mat4::rotate_y(Angle::from_degrees(-50.0))
                         * mat4::rotate_z(Angle::from_degrees(180.0)),
                 );
@@ -816,18 +1451,79 @@ impl Game {
             }
         }
 
-        for &tile in &self.raft {
+        for (&tile, &kind) in &self.raft {
             self.ctx.model_draw.draw(
                 framebuffer,
                 &self.camera,
-                &self.ctx.assets.raft_tile,
+                self.ctx.assets.tile(kind),
                 mat4::translate(
                     (tile.map(|x| x as f32) * self.ctx.assets.config.tile_size).extend(0.0),
                 ) * self.tile_transform(tile),
             );
         }
+        for (fragment, sinking) in self.fragments.values() {
+            let z = -sinking.unwrap_or(0.0) * self.ctx.assets.config.raft_physics.sink_speed;
+            for (&tile, &kind) in &fragment.tiles {
+                self.ctx.model_draw.draw(
+                    framebuffer,
+                    &self.camera,
+                    self.ctx.assets.tile(kind),
+                    mat4::translate(
+                        (tile.map(|x| x as f32) * self.ctx.assets.config.tile_size
+                            + fragment.offset)
+                            .extend(z),
+                    ) * self.tile_transform(tile),
+                );
+            }
+        }
+        for &(tile, kind, time) in &self.sinking_tiles {
+            self.ctx.model_draw.draw(
+                framebuffer,
+                &self.camera,
+                self.ctx.assets.tile(kind),
+                mat4::translate(
+                    (tile.map(|x| x as f32) * self.ctx.assets.config.tile_size)
+                        .extend(-time * self.ctx.assets.config.raft_physics.sink_speed),
+                ) * self.tile_transform(tile)
+                    * mat4::rotate_x(Angle::from_degrees(time * 20.0)),
+            );
+        }
 
-        if let Some(bb) = Aabb2::points_bounding_box(self.raft.iter().copied()) {
+        for item in self.items.values() {
+            let pos = item.pos.extend(self.ctx.assets.config.water.z);
+            self.ctx.model_draw.draw(
+                framebuffer,
+                &self.camera,
+                &self.ctx.assets.raft_tile,
+                mat4::translate(pos)
+                    * mat4::rotate_z(Angle::from_radians(self.time))
+                    * mat4::scale_uniform(0.3),
+            );
+            self.draw_name(
+                framebuffer,
+                item.power_up.name(),
+                Pos {
+                    pos,
+                    rot: Angle::ZERO,
+                    vel: vec3::ZERO,
+                },
+            );
+        }
+
+        for &(tile, time) in self.repairs.values() {
+            let progress = (time / self.ctx.assets.config.repair.time).min(1.0);
+            self.ctx.model_draw.draw(
+                framebuffer,
+                &self.camera,
+                &self.ctx.assets.raft_tile,
+                mat4::translate(
+                    (tile.map(|x| x as f32) * self.ctx.assets.config.tile_size).extend(0.0),
+                ) * self.tile_transform(tile)
+                    * mat4::scale_uniform(progress),
+            );
+        }
+
+        if let Some(bb) = Aabb2::points_bounding_box(self.raft.keys().copied()) {
             let bb = bb.extend_uniform(1);
             let mut raft_texture = ugli::Texture::new_with(
                 self.ctx.geng.ugli(),
@@ -835,7 +1531,7 @@ impl Game {
                 |pos| {
                     if self
                         .raft
-                        .contains(&(pos.map(|x| x as i32) + bb.bottom_left()))
+                        .contains_key(&(pos.map(|x| x as i32) + bb.bottom_left()))
                     {
                         Rgba::WHITE
                     } else {
@@ -844,7 +1540,7 @@ impl Game {
                 },
             );
             raft_texture.set_filter(ugli::Filter::Nearest);
-            for poop in &self.floor_poop {
+            for poop in self.floor_poop.values() {
                 self.ctx.model_draw.draw_masked(
                     framebuffer,
                     &self.camera,
@@ -926,6 +1622,7 @@ impl Game {
                     self.draw_name(framebuffer, name, other.pos.get());
                 }
             }
+            self.draw_hud(framebuffer);
         }
 
         if self.ctx.geng.window().is_key_pressed(geng::Key::Tab) {
@@ -933,16 +1630,108 @@ impl Game {
         }
     }
 
+    fn draw_hud(&self, framebuffer: &mut ugli::Framebuffer) {
+        let mut lines = Vec::new();
+        if let Some(sudden_death_at) = self.sudden_death_at {
+            let time = (sudden_death_at - self.clock.now()) as f32;
+            if time > 0.0 {
+                let time = time.ceil() as i32;
+                lines.push(format!("sudden death in {}:{:02}", time / 60, time % 60));
+            } else {
+                lines.push("SUDDEN DEATH".to_owned());
+            }
+        }
+        if self.ctx.assets.config.respawn.lives > 1 && self.name != SPECTATOR_STR {
+            lines.push(format!("lives: {}", self.my_lives));
+            if let Some(time) = self.respawn_timer {
+                lines.push(format!("respawn in {}", time.ceil().max(0.0) as i32));
+            }
+        }
+        if let Some(mutator) = self.mutator {
+            lines.push(format!("mutator: {}", mutator.name()));
+        }
+        for (power_up, time) in &self.power_ups {
+            lines.push(format!("{}: {}", power_up.name(), time.ceil() as i32));
+        }
+        if self.show_net_stats {
+            match self.clock.rtt() {
+                Some(rtt) => lines.push(format!("rtt: {:.0} ms", rtt * 1000.0)),
+                None => lines.push("rtt: -".to_owned()),
+            }
+            lines.push(format!("jitter: {:.0} ms", self.clock.jitter() * 1000.0));
+            lines.push(format!("clock offset: {:.3} s", self.clock.offset()));
+            lines.push(format!("messages/s: {:.0}", self.messages_per_second));
+        }
+        if self.me.is_none() && self.name != SPECTATOR_STR && !self.naming {
+            lines.push("vote for next round:".to_owned());
+            for (i, mutator) in Mutator::ALL.into_iter().enumerate() {
+                let mark = if self.my_vote == Some(mutator) {
+                    " <"
+                } else {
+                    ""
+                };
+                lines.push(format!("{}: {}{mark}", i + 1, mutator.name()));
+            }
+        }
+
+        let camera = geng::Camera2d {
+            center: vec2::ZERO,
+            rotation: Angle::ZERO,
+            fov: 20.0,
+        };
+        let font = self.ctx.geng.default_font();
+        let top_left = vec2(
+            -camera.fov / 2.0 * self.framebuffer_size.aspect(),
+            camera.fov / 2.0,
+        );
+        for (i, line) in lines.iter().enumerate() {
+            font.draw(
+                framebuffer,
+                &camera,
+                line,
+                vec2(geng::TextAlign::LEFT, geng::TextAlign::TOP),
+                mat3::translate(top_left + vec2(0.5, -0.5 - i as f32)),
+                Rgba::BLACK,
+            );
+        }
+
+        if self.map_name_timer > 0.0 {
+            font.draw(
+                framebuffer,
+                &camera,
+                &self.map_name,
+                vec2::splat(geng::TextAlign::CENTER),
+                mat3::translate(vec2(0.0, 5.0)) * mat3::scale_uniform(2.0),
+                Rgba::BLACK,
+            );
+            if let Some(mutator) = self.mutator {
+                font.draw(
+                    framebuffer,
+                    &camera,
+                    mutator.name(),
+                    vec2::splat(geng::TextAlign::CENTER),
+                    mat3::translate(vec2(0.0, 3.0)),
+                    Rgba::BLACK,
+                );
+            }
+        }
+    }
+
     fn draw_leaderboard(&self, framebuffer: &mut ugli::Framebuffer) {
+        let show_lives = self.ctx.assets.config.respawn.lives > 1;
         let mut lb: Vec<_> = self
             .scores
             .iter()
             .map(|(id, score)| {
-                let name = self.names.get(id).unwrap_or(&self.name);
-                (name, score)
+                let name = match self.names.get(id) {
+                    Some(name) => name.as_str(),
+                    None if self.sharks.contains_key(id) => "shark",
+                    None => self.name.as_str(),
+                };
+                (id, name, score)
             })
             .collect();
-        lb.sort_by_key(|&(name, score)| (-(score.points as i32), name));
+        lb.sort_by_key(|&(_, name, score)| (-(score.points as i32), name));
         let camera = geng::Camera2d {
             center: vec2::ZERO,
             rotation: Angle::ZERO,
@@ -952,7 +1741,7 @@ impl Game {
         let font = self.ctx.geng.default_font();
 
         let mut y = lb.len() as f32 / 2.0;
-        let mut draw_row = |name: &str, elim: &str, wins: &str, points: &str, color: Rgba<f32>| {
+        let mut draw_row = |name: &str, columns: &[&str], color: Rgba<f32>| {
             font.draw(
                 framebuffer,
                 &camera,
@@ -961,42 +1750,40 @@ impl Game {
                 mat3::translate(vec2(-5.0, y)),
                 color,
             );
-            font.draw(
-                framebuffer,
-                &camera,
-                elim,
-                vec2(geng::TextAlign::RIGHT, geng::TextAlign::BOTTOM),
-                mat3::translate(vec2(1.0, y)),
-                color,
-            );
-            font.draw(
-                framebuffer,
-                &camera,
-                wins,
-                vec2(geng::TextAlign::RIGHT, geng::TextAlign::BOTTOM),
-                mat3::translate(vec2(3.0, y)),
-                color,
-            );
-            font.draw(
-                framebuffer,
-                &camera,
-                points,
-                vec2(geng::TextAlign::RIGHT, geng::TextAlign::BOTTOM),
-                mat3::translate(vec2(5.0, y)),
-                color,
-            );
+            for (i, column) in columns.iter().enumerate() {
+                font.draw(
+                    framebuffer,
+                    &camera,
+                    column,
+                    vec2(geng::TextAlign::RIGHT, geng::TextAlign::BOTTOM),
+                    mat3::translate(vec2(1.0 + 2.0 * i as f32, y)),
+                    color,
+                );
+            }
             y -= 1.0;
         };
 
-        draw_row("name", "elims", "wins", "pts", Rgba::GRAY);
-        for (name, score) in lb {
-            draw_row(
-                name,
-                &score.eliminations.to_string(),
-                &score.wins.to_string(),
-                &score.points.to_string(),
-                Rgba::BLACK,
-            );
+        let mut header = vec!["elims", "wins", "pts"];
+        if show_lives {
+            header.push("lives");
+        }
+        header.push("ping");
+        draw_row("name", &header, Rgba::GRAY);
+        for (id, name, score) in lb {
+            let mut row = vec![
+                score.eliminations.to_string(),
+                score.wins.to_string(),
+                score.points.to_string(),
+            ];
+            if show_lives {
+                row.push(self.lives.get(id).copied().unwrap_or(0).to_string());
+            }
+            row.push(match self.pings.get(id) {
+                Some(ping) => format!("{}", (ping * 1000.0).round() as i32),
+                None => "-".to_owned(),
+            });
+            let row: Vec<&str> = row.iter().map(|s| s.as_str()).collect();
+            draw_row(name, &row, Rgba::BLACK);
         }
     }
 
@@ -1011,19 +1798,37 @@ impl Game {
 
     fn tile_transform(&self, pos: vec2<i32>) -> mat4<f32> {
         let (wave_z, wave_angle) = {
-            let (wave_sin, wave_cos) = ((vec2::dot(
+            let config = &self.ctx.assets.config.wave;
+            // wrapped in f64 so the phase stays precise on long-running servers
+            let time_phase = (self.clock.now() * config.speed as f64 * config.freq as f64)
+                .rem_euclid(std::f64::consts::TAU) as f32;
+            let (wave_sin, wave_cos) = (vec2::dot(
                 pos.map(|x| x as f32) * self.ctx.assets.config.tile_size,
                 self.wave_dir,
-            ) + self.time * self.ctx.assets.config.wave.speed)
-                * self.ctx.assets.config.wave.freq)
+            ) * config.freq
+                + time_phase)
                 .sin_cos();
             (
-                wave_sin * self.ctx.assets.config.wave.vertical_amp,
-                Angle::from_degrees(-wave_cos * self.ctx.assets.config.wave.angle_amp),
+                wave_sin * config.vertical_amp,
+                Angle::from_degrees(-wave_cos * config.angle_amp),
             )
         };
-        mat4::translate(vec3(0.0, 0.0, wave_z))
-            * mat4::rotate(self.wave_dir.rotate_90().extend(0.0), wave_angle)
+        let load = self
+            .raft_load
+            .get(&pos)
+            .copied()
+            .unwrap_or(map::TileLoad::NONE);
+        let mut transform = mat4::translate(vec3(0.0, 0.0, wave_z - load.sag))
+            * mat4::rotate(self.wave_dir.rotate_90().extend(0.0), wave_angle);
+        if load.tilt.len() > 1e-5 {
+            transform *= mat4::rotate(
+                load.tilt.rotate_90().extend(0.0),
+                Angle::from_degrees(
+                    load.tilt.len() * self.ctx.assets.config.raft_physics.tilt_angle,
+                ),
+            );
+        }
+        transform
     }
 
     fn draw_name(&self, framebuffer: &mut ugli::Framebuffer<'_>, name: &str, pos: Pos) {
@@ -1097,7 +1902,10 @@ fn main() {
     if cli.server.is_some() && cli.connect.is_none() {
         #[cfg(not(target_arch = "wasm32"))]
         {
-            let server = geng::net::Server::new(server::App::new(), cli.server.as_deref().unwrap());
+            let server = geng::net::Server::new(
+                server::App::new(cli.net_sim.clone()),
+                cli.server.as_deref().unwrap(),
+            );
             let server_handle = server.handle();
             ctrlc::set_handler(move || server_handle.shutdown()).unwrap();
             server.run();
@@ -1105,7 +1913,8 @@ fn main() {
     } else {
         #[cfg(not(target_arch = "wasm32"))]
         let server = if let Some(addr) = &cli.server {
-            let server = geng::net::Server::new(server::App::new(), addr);
+            // The client simulates both directions already, don't double it up
+            let server = geng::net::Server::new(server::App::new(default()), addr);
             let server_handle = server.handle();
             let server_thread = std::thread::spawn(move || {
                 server.run();
@@ -1127,7 +1936,8 @@ fn main() {
                 let con = geng::net::client::connect(cli.connect.as_deref().unwrap())
                     .await
                     .unwrap();
-                Game::new(&ctx, con).run().await;
+                let con = net_sim::Connection::new(con, cli.net_sim.clone());
+                Game::new(&ctx, con, cli.snapshot_rate).run().await;
             },
         );
 
@@ -0,0 +1,316 @@
+use super::*;
+
+pub const NEIGHBOURS: [vec2<i32>; 4] = [vec2(-1, 0), vec2(1, 0), vec2(0, -1), vec2(0, 1)];
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum Layout {
+    Disc,
+    Ring,
+    Islands,
+    Noise,
+    Cross,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[serde(rename_all = "snake_case")]
+pub enum TileKind {
+    Plain,
+    Reinforced,
+    Slippery,
+    Bouncy,
+}
+
+impl TileKind {
+    pub const ALL: [Self; 4] = [Self::Plain, Self::Reinforced, Self::Slippery, Self::Bouncy];
+    pub fn bitten(self) -> Option<Self> {
+        match self {
+            Self::Reinforced => Some(Self::Plain),
+            Self::Plain | Self::Slippery | Self::Bouncy => None,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+pub struct TileLoad {
+    pub sag: f32,
+    pub tilt: vec2<f32>,
+}
+
+impl TileLoad {
+    pub const NONE: Self = Self {
+        sag: 0.0,
+        tilt: vec2::ZERO,
+    };
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Fragment {
+    pub tiles: HashMap<vec2<i32>, TileKind>,
+    pub offset: vec2<f32>,
+    pub vel: vec2<f32>,
+}
+
+impl Fragment {
+    pub fn tile_at(&self, pos: vec2<f32>, tile_size: f32) -> vec2<i32> {
+        (pos - self.offset).map(|x| (x / tile_size).round() as i32)
+    }
+    pub fn contains(&self, pos: vec2<f32>, tile_size: f32) -> bool {
+        self.tiles.contains_key(&self.tile_at(pos, tile_size))
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+pub enum RotationEntry {
+    Generate,
+    File(String),
+}
+
+#[derive(Deserialize)]
+pub struct MapFile {
+    pub name: String,
+    pub rows: Vec<String>,
+}
+
+#[derive(Clone)]
+pub struct Map {
+    pub name: String,
+    pub raft: HashMap<vec2<i32>, TileKind>,
+    pub spawns: Vec<vec2<i32>>,
+}
+
+fn disc(center: vec2<i32>, radius: f32) -> impl Iterator<Item = vec2<i32>> {
+    Aabb2::ZERO
+        .extend_uniform(radius.ceil() as i32)
+        .extend_positive(vec2::splat(1))
+        .points()
+        .filter(move |delta| delta.map(|x| x as f32).len() <= radius + 0.5)
+        .map(move |delta| center + delta)
+}
+
+fn bridge(from: vec2<i32>, to: vec2<i32>) -> Vec<vec2<i32>> {
+    let mut tile = from;
+    let mut result = vec![tile];
+    while tile != to {
+        let delta = to - tile;
+        if delta.x.abs() >= delta.y.abs() {
+            tile += vec2(delta.x.signum(), 0);
+        } else {
+            tile += vec2(0, delta.y.signum());
+        }
+        result.push(tile);
+    }
+    result
+}
+
+pub fn component<T>(raft: &HashMap<vec2<i32>, T>, start: vec2<i32>) -> HashSet<vec2<i32>> {
+    let mut result = HashSet::new();
+    if !raft.contains_key(&start) {
+        return result;
+    }
+    result.insert(start);
+    let mut queue = vec![start];
+    while let Some(tile) = queue.pop() {
+        for d in NEIGHBOURS {
+            let next = tile + d;
+            if raft.contains_key(&next) && result.insert(next) {
+                queue.push(next);
+            }
+        }
+    }
+    result
+}
+
+pub fn components<T: Clone>(raft: &HashMap<vec2<i32>, T>) -> Vec<HashSet<vec2<i32>>> {
+    let mut left = raft.clone();
+    let mut result = Vec::new();
+    loop {
+        let Some(&start) = left.keys().next() else {
+            break;
+        };
+        let component = component(&left, start);
+        left.retain(|tile, _| !component.contains(tile));
+        result.push(component);
+    }
+    result
+}
+
+impl Map {
+    pub fn from_file(file: MapFile) -> anyhow::Result<Self> {
+        let height = file.rows.len() as i32;
+        let width = file
+            .rows
+            .iter()
+            .map(|row| row.chars().count())
+            .max()
+            .unwrap_or(0) as i32;
+        let mut raft = HashMap::new();
+        let mut spawns = Vec::new();
+        for (y, row) in file.rows.iter().enumerate() {
+            for (x, c) in row.chars().enumerate() {
+                let tile = vec2(x as i32 - width / 2, height / 2 - y as i32);
+                let kind = match c {
+                    ' ' => continue,
+                    '#' => TileKind::Plain,
+                    'S' => {
+                        spawns.push(tile);
+                        TileKind::Plain
+                    }
+                    'R' => TileKind::Reinforced,
+                    '~' => TileKind::Slippery,
+                    'B' => TileKind::Bouncy,
+                    _ => anyhow::bail!("Unexpected tile {c:?} in map {:?}", file.name),
+                };
+                raft.insert(tile, kind);
+            }
+        }
+        anyhow::ensure!(!spawns.is_empty(), "No spawns in map {:?}", file.name);
+        anyhow::ensure!(
+            components(&raft).len() == 1,
+            "Map {:?} is not one connected raft",
+            file.name,
+        );
+        Ok(Self {
+            name: file.name,
+            raft,
+            spawns,
+        })
+    }
+    pub fn generate(layout: Layout, size: i32, config: &assets::RaftGenConfig) -> Self {
+        let size = size.max(1);
+        let (raft, spawns): (HashSet<vec2<i32>>, Vec<vec2<i32>>) = match layout {
+            Layout::Disc => (disc(vec2::ZERO, size as f32).collect(), vec![vec2::ZERO]),
+            Layout::Ring => {
+                let hole = config.ring_hole.min(size as f32 - 2.0);
+                (
+                    disc(vec2::ZERO, size as f32)
+                        .filter(|tile| tile.map(|x| x as f32).len() > hole + 0.5)
+                        .collect(),
+                    vec![vec2(size, 0), vec2(-size, 0), vec2(0, size), vec2(0, -size)],
+                )
+            }
+            Layout::Islands => {
+                let count = config.islands.max(2);
+                let radius = (size + config.island_size) as f32;
+                let centers: Vec<vec2<i32>> = (0..count)
+                    .map(|i| {
+                        vec2(radius, 0.0)
+                            .rotate(Angle::from_degrees(360.0 * i as f32 / count as f32))
+                            .map(|x| x.round() as i32)
+                    })
+                    .collect();
+                let mut raft = HashSet::new();
+                for (i, &center) in centers.iter().enumerate() {
+                    raft.extend(disc(center, config.island_size as f32));
+                    raft.extend(bridge(center, centers[(i + 1) % count]));
+                }
+                (raft, centers)
+            }
+            Layout::Noise => {
+                let noise = noise::Perlin::new(thread_rng().gen());
+                let mut raft: HashSet<vec2<i32>> = disc(vec2::ZERO, size as f32 + 1.0)
+                    .filter(|tile| {
+                        let p = tile.map(|x| x as f64 * config.noise_scale);
+                        noise::NoiseFn::get(&noise, [p.x, p.y]) > config.noise_threshold
+                    })
+                    .collect();
+                raft.extend(disc(vec2::ZERO, 1.0));
+                (raft, vec![vec2::ZERO])
+            }
+            Layout::Cross => (
+                Aabb2::ZERO
+                    .extend_uniform(size + 1)
+                    .extend_positive(vec2::splat(1))
+                    .points()
+                    .filter(|tile| tile.x.abs() <= 1 || tile.y.abs() <= 1)
+                    .collect(),
+                vec![vec2::ZERO],
+            ),
+        };
+        let total_weight: f64 = TileKind::ALL
+            .into_iter()
+            .map(|kind| *config.kinds.get(kind))
+            .sum();
+        let mut raft: HashMap<vec2<i32>, TileKind> = raft
+            .into_iter()
+            .map(|tile| {
+                let mut x = thread_rng().gen_range(0.0..total_weight.max(1e-9));
+                let kind = TileKind::ALL
+                    .into_iter()
+                    .find(|&kind| {
+                        x -= *config.kinds.get(kind);
+                        x < 0.0
+                    })
+                    .unwrap_or(TileKind::Plain);
+                (tile, kind)
+            })
+            .collect();
+        let connected = component(&raft, spawns[0]);
+        raft.retain(|tile, _| connected.contains(tile));
+        let spawns = spawns
+            .into_iter()
+            .filter(|spawn| raft.contains_key(spawn))
+            .collect();
+        Self {
+            name: format!("{layout:?}"),
+            raft,
+            spawns,
+        }
+    }
+}
+
+#[test]
+fn test_layouts_connected() {
+    let config = assets::RaftGenConfig {
+        layouts: vec![],
+        ring_hole: 1.0,
+        islands: 3,
+        island_size: 1,
+        noise_scale: 0.4,
+        noise_threshold: -0.2,
+        kinds: assets::PerTileKind {
+            plain: 1.0,
+            reinforced: 1.0,
+            slippery: 1.0,
+            bouncy: 1.0,
+        },
+    };
+    for layout in [
+        Layout::Disc,
+        Layout::Ring,
+        Layout::Islands,
+        Layout::Noise,
+        Layout::Cross,
+    ] {
+        for size in 1..6 {
+            let map = Map::generate(layout, size, &config);
+            assert!(!map.spawns.is_empty());
+            assert_eq!(components(&map.raft).len(), 1);
+            assert!(map.spawns.iter().all(|spawn| map.raft.contains_key(spawn)));
+        }
+    }
+}
+
+#[test]
+fn test_map_files() {
+    let maps_dir = std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("assets")
+        .join("maps");
+    let rotation: Vec<RotationEntry> =
+        futures::executor::block_on(file::load_detect(maps_dir.join("list.ron"))).unwrap();
+    for entry in rotation {
+        if let RotationEntry::File(name) = entry {
+            let map_file: MapFile = futures::executor::block_on(file::load_detect(
+                maps_dir.join(format!("{name}.ron")),
+            ))
+            .unwrap();
+            Map::from_file(map_file).unwrap();
+        }
+    }
+
+    let split = MapFile {
+        name: "Split".to_owned(),
+        rows: vec!["#S# #S#".to_owned()],
+    };
+    assert!(Map::from_file(split).is_err());
+}
@@ -50,13 +50,24 @@ impl ModelDraw {
         model: &pog_paint::Model,
         transform: mat4<f32>,
     ) {
-        self.draw_masked(
+        self.draw_colored(framebuffer, camera, model, transform, Rgba::WHITE);
+    }
+
+    pub fn draw_colored(
+        &self,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &impl AbstractCamera3d,
+        model: &pog_paint::Model,
+        transform: mat4<f32>,
+        color: Rgba<f32>,
+    ) {
+        self.draw_impl(
             framebuffer,
             camera,
             model,
             transform,
-            &self.white_texture,
-            mat3::identity(),
+            (&self.white_texture, mat3::identity()),
+            color,
         );
     }
 
@@ -68,6 +79,25 @@ impl ModelDraw {
         transform: mat4<f32>,
         mask: &ugli::Texture,
         mask_transform: mat3<f32>,
+    ) {
+        self.draw_impl(
+            framebuffer,
+            camera,
+            model,
+            transform,
+            (mask, mask_transform),
+            Rgba::WHITE,
+        );
+    }
+
+    fn draw_impl(
+        &self,
+        framebuffer: &mut ugli::Framebuffer,
+        camera: &impl AbstractCamera3d,
+        model: &pog_paint::Model,
+        transform: mat4<f32>,
+        (mask, mask_transform): (&ugli::Texture, mat3<f32>),
+        color: Rgba<f32>,
     ) {
         let transform = transform
             * mat4::scale_uniform(1.0 / self.assets.config.scaling)
@@ -90,6 +120,7 @@ impl ModelDraw {
                             u_model_matrix: model_matrix,
                             u_mask: mask,
                             u_mask_matrix: mask_transform,
+                            u_color: color,
                         },
                         camera.uniforms(framebuffer_size),
                     ),
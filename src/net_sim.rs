@@ -0,0 +1,240 @@
+use super::*;
+
+use std::collections::VecDeque;
+
+/// Bad network conditions to reproduce lag bugs locally, all off by default
+#[derive(clap::Args, Clone, Debug, Default)]
+pub struct NetSim {
+    /// Extra one-way delay added to every message, in milliseconds
+    #[clap(long = "sim-latency", default_value_t = 0.0)]
+    pub latency: f64,
+    /// Random extra delay on top of latency, up to this many milliseconds
+    #[clap(long = "sim-jitter", default_value_t = 0.0)]
+    pub jitter: f64,
+    /// Chance (0 to 1) that a message is not held back behind earlier ones
+    #[clap(long = "sim-reorder", default_value_t = 0.0)]
+    pub reorder: f64,
+    /// Chance (0 to 1) that a message is lost, the game assumes reliable delivery so expect breakage
+    #[clap(long = "sim-drop", default_value_t = 0.0)]
+    pub drop: f64,
+}
+
+impl NetSim {
+    pub fn is_enabled(&self) -> bool {
+        self.latency > 0.0 || self.jitter > 0.0 || self.reorder > 0.0 || self.drop > 0.0
+    }
+}
+
+/// Messages waiting to be delivered at their simulated arrival time
+pub struct Queue<T> {
+    sim: NetSim,
+    rng: rand::rngs::StdRng,
+    timer: Timer,
+    last_due: f64,
+    messages: VecDeque<(f64, T)>,
+}
+
+impl<T> Queue<T> {
+    pub fn new(sim: NetSim) -> Self {
+        Self::seeded(sim, thread_rng().gen())
+    }
+
+    fn seeded(sim: NetSim, seed: u64) -> Self {
+        use rand::SeedableRng;
+        Self {
+            sim,
+            rng: rand::rngs::StdRng::seed_from_u64(seed),
+            timer: Timer::new(),
+            last_due: 0.0,
+            messages: VecDeque::new(),
+        }
+    }
+
+    fn now(&self) -> f64 {
+        self.timer.elapsed().as_secs_f64()
+    }
+
+    pub fn push(&mut self, message: T) {
+        self.push_at(self.now(), message);
+    }
+
+    fn push_at(&mut self, now: f64, message: T) {
+        if self.rng.gen::<f64>() < self.sim.drop {
+            return;
+        }
+        let delay = self.sim.latency + self.rng.gen::<f64>() * self.sim.jitter;
+        let mut due = now + delay / 1000.0;
+        // Websockets keep order, so only reordered messages may overtake the ones before them
+        if self.rng.gen::<f64>() >= self.sim.reorder {
+            due = due.max(self.last_due);
+            self.last_due = due;
+        }
+        let index = self.messages.partition_point(|&(t, _)| t <= due);
+        self.messages.insert(index, (due, message));
+    }
+
+    pub fn pop_due(&mut self) -> Option<T> {
+        self.pop_due_at(self.now())
+    }
+
+    fn pop_due_at(&mut self, now: f64) -> Option<T> {
+        let &(due, _) = self.messages.front()?;
+        if due > now {
+            return None;
+        }
+        self.messages.pop_front().map(|(_, message)| message)
+    }
+
+    /// Seconds until the next message is due
+    pub fn next_due_in(&self) -> Option<f64> {
+        let &(due, _) = self.messages.front()?;
+        Some((due - self.now()).max(0.0))
+    }
+}
+
+/// Client connection that encodes messages and delays them both ways, pumped by `new_messages`
+pub struct Connection<S: geng::net::Message, C: geng::net::Message> {
+    inner: geng::net::client::Connection<encoding::Packet, encoding::Packet>,
+    queues: Option<(Queue<C>, Queue<S>)>,
+}
+
+impl<S: geng::net::Message, C: geng::net::Message> Connection<S, C> {
+    pub fn new(
+        inner: geng::net::client::Connection<encoding::Packet, encoding::Packet>,
+        sim: NetSim,
+    ) -> Self {
+        let queues = sim
+            .is_enabled()
+            .then(|| (Queue::new(sim.clone()), Queue::new(sim)));
+        Self { inner, queues }
+    }
+
+    pub fn send(&mut self, message: C) {
+        match &mut self.queues {
+            Some((outgoing, _)) => outgoing.push(message),
+            None => self.inner.send(encoding::Packet::encode(&message)),
+        }
+    }
+
+    pub fn new_messages(&mut self) -> Vec<S> {
+        let received = self
+            .inner
+            .new_messages()
+            .map(|packet| packet.unwrap().decode::<S>().unwrap());
+        let Some((outgoing, incoming)) = &mut self.queues else {
+            return received.collect();
+        };
+        for message in received {
+            incoming.push(message);
+        }
+        while let Some(message) = outgoing.pop_due() {
+            self.inner.send(encoding::Packet::encode(&message));
+        }
+        std::iter::from_fn(|| incoming.pop_due()).collect()
+    }
+}
+
+/// Delivers messages on its own thread once they are due, used on the server
+#[cfg(not(target_arch = "wasm32"))]
+pub struct Delayed<T> {
+    sender: Option<std::sync::mpsc::Sender<T>>,
+    thread: Option<std::thread::JoinHandle<()>>,
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: Send + 'static> Delayed<T> {
+    pub fn new(sim: NetSim, mut deliver: impl FnMut(T) + Send + 'static) -> Self {
+        use std::sync::mpsc::RecvTimeoutError;
+        let (sender, receiver) = std::sync::mpsc::channel();
+        let thread = std::thread::spawn(move || {
+            let mut queue = Queue::new(sim);
+            loop {
+                let received = match queue.next_due_in() {
+                    Some(time) => receiver.recv_timeout(std::time::Duration::from_secs_f64(time)),
+                    None => receiver.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                };
+                match received {
+                    Ok(message) => queue.push(message),
+                    Err(RecvTimeoutError::Timeout) => {}
+                    Err(RecvTimeoutError::Disconnected) => break,
+                }
+                while let Some(message) = queue.pop_due() {
+                    deliver(message);
+                }
+            }
+        });
+        Self {
+            sender: Some(sender),
+            thread: Some(thread),
+        }
+    }
+
+    pub fn send(&self, message: T) {
+        if let Some(sender) = &self.sender {
+            let _ = sender.send(message);
+        }
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl<T: geng::net::Message + Send + 'static> geng::net::Sender<T> for Delayed<T> {
+    fn send(&mut self, message: T) {
+        Delayed::send(self, message);
+    }
+}
+
+/// Pending messages are thrown away, so nothing is delivered after this is dropped
+#[cfg(not(target_arch = "wasm32"))]
+impl<T> Drop for Delayed<T> {
+    fn drop(&mut self) {
+        self.sender.take();
+        if let Some(thread) = self.thread.take() {
+            if thread.join().is_err() {
+                log::error!("Network simulation thread panicked");
+            }
+        }
+    }
+}
+
+/// Pushes `0..count` at once and returns what comes out, in delivery order
+#[cfg(test)]
+fn deliver(sim: NetSim, count: i32) -> Vec<i32> {
+    let mut queue = Queue::seeded(sim, 42);
+    for i in 0..count {
+        queue.push_at(0.0, i);
+    }
+    std::iter::from_fn(|| queue.pop_due_at(f64::INFINITY)).collect()
+}
+
+#[test]
+fn test_queue_keeps_order() {
+    let sim = NetSim {
+        latency: 10.0,
+        jitter: 20.0,
+        ..default()
+    };
+    assert_eq!(deliver(sim, 100), (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_queue_reorder() {
+    let sim = NetSim {
+        latency: 10.0,
+        jitter: 20.0,
+        reorder: 1.0,
+        ..default()
+    };
+    let mut received = deliver(sim, 100);
+    assert!(received.windows(2).any(|pair| pair[0] > pair[1]));
+    received.sort();
+    assert_eq!(received, (0..100).collect::<Vec<_>>());
+}
+
+#[test]
+fn test_queue_drop() {
+    let sim = NetSim {
+        drop: 1.0,
+        ..default()
+    };
+    assert!(deliver(sim, 100).is_empty());
+}
@@ -1,24 +1,72 @@
 use super::*;
 
+mod shark_ai;
+mod traffic;
+
 struct State {
     names: HashMap<Id, String>,
-    attacks: HashMap<Id, (vec2<f32>, f32)>,
+    attacks: HashMap<Id, (vec2<f32>, f32, f32)>,
     should_exit: bool,
+    clock: Timer,
+    pog_sent: HashMap<Id, f64>,
+    snapshot_streams: HashMap<Id, SnapshotStream>,
+    pings: HashMap<Id, f64>,
+    ping_timer: f32,
+    traffic: Arc<Mutex<traffic::Traffic>>,
+    traffic_timer: f32,
     config: assets::Config,
+    base_config: assets::Config,
+    mutator: Option<Mutator>,
+    votes: HashMap<Id, Mutator>,
     id_gen: IdGen,
     player_pos: HashMap<Id, Pos>,
     gull_pos: HashMap<Id, Pos>,
-    raft: HashSet<vec2<i32>>,
+    raft: HashMap<vec2<i32>, map::TileKind>,
+    raft_load: HashMap<vec2<i32>, map::TileLoad>,
+    fragments: HashMap<Id, (map::Fragment, f32)>,
     senders: HashMap<Id, Box<dyn geng::net::Sender<ServerMessage>>>,
     sharks: HashMap<Id, Shark>,
     restart_timer: Option<f32>,
     dash_cooldowns: HashMap<Id, f32>,
     poop_cooldowns: HashMap<Id, f32>,
+    dive_cooldowns: HashMap<Id, f32>,
+    braces: HashMap<Id, f32>,
+    brace_cooldowns: HashMap<Id, f32>,
+    plank_cooldowns: HashMap<Id, f32>,
     wait_for_teleport_ack: HashSet<Id>,
     flying_poops: Vec<Pos>,
+    flying_planks: Vec<Pos>,
+    floor_poops: HashMap<Id, (vec2<f32>, f32)>,
+    sliding: HashMap<Id, vec2<f32>>,
+    stuns: HashMap<Id, f32>,
     scores: HashMap<Id, Score>,
     last_touch: HashMap<Id, (Id, Timer)>,
     reset: bool,
+    lives: HashMap<Id, usize>,
+    respawn_timers: HashMap<Id, f32>,
+    round_time: f32,
+    sudden_death_at: f64,
+    shrink_timer: f32,
+    rotation: Vec<map::RotationEntry>,
+    map_files: HashMap<String, map::Map>,
+    rotation_index: usize,
+    map_name: String,
+    repairs: HashMap<Id, (vec2<i32>, f32)>,
+    items: HashMap<Id, (Item, f32)>,
+    item_timer: f32,
+    effects: HashMap<Id, HashMap<PowerUp, f32>>,
+}
+
+struct SnapshotStream {
+    requested_rate: f32,
+    timer: f32,
+    interval: f32,
+}
+
+fn has_effect(effects: &HashMap<Id, HashMap<PowerUp, f32>>, id: Id, power_up: PowerUp) -> bool {
+    effects
+        .get(&id)
+        .map_or(false, |effects| effects.contains_key(&power_up))
 }
 
 fn intersect(from: vec2<f32>, dir: vec2<f32>, center: vec2<f32>, radius: f32) -> Option<f32> {
@@ -37,6 +85,28 @@ fn intersect(from: vec2<f32>, dir: vec2<f32>, center: vec2<f32>, radius: f32) ->
     (t > 0.0).then_some(t)
 }
 
+fn new_shark(config: &assets::Config, index: usize) -> Shark {
+    let pos = thread_rng().gen_circle(vec2::ZERO, config.raft_size as f32 * config.tile_size);
+    Shark {
+        behavior: config
+            .shark
+            .behaviors
+            .get(index % config.shark.behaviors.len().max(1))
+            .copied()
+            .unwrap_or(SharkBehavior::Random),
+        destroy: None,
+        destroy_timer: None,
+        grab: None,
+        grab_timer: None,
+        pos: Pos {
+            pos: pos.extend(config.shark.depth),
+            rot: thread_rng().gen(),
+            vel: vec3::ZERO,
+        },
+        target_pos: pos,
+    }
+}
+
 struct IdGen {
     last_id: Id,
 }
@@ -49,65 +119,488 @@ impl IdGen {
 }
 
 impl State {
+    const TRAFFIC_REPORT_INTERVAL: f32 = 10.0;
+
+    fn next_mutator(&mut self) -> Option<Mutator> {
+        let mut tally: HashMap<Mutator, usize> = HashMap::new();
+        for (_, mutator) in self.votes.drain() {
+            *tally.entry(mutator).or_default() += 1;
+        }
+        if let Some(&max) = tally.values().max() {
+            return tally
+                .into_iter()
+                .filter(|&(_, count)| count == max)
+                .map(|(mutator, _)| mutator)
+                .choose(&mut thread_rng());
+        }
+        if thread_rng().gen_bool(self.base_config.mutators.chance) {
+            Mutator::ALL.into_iter().choose(&mut thread_rng())
+        } else {
+            None
+        }
+    }
     fn restart(&mut self) {
+        self.mutator = self.next_mutator();
+        self.config = self.base_config.clone();
+        if let Some(mutator) = self.mutator {
+            mutator.apply(&mut self.config);
+        }
+        while self.sharks.len() > self.config.shark.count {
+            let id = *self.sharks.keys().max().unwrap();
+            self.sharks.remove(&id);
+        }
+        while self.sharks.len() < self.config.shark.count {
+            let shark = new_shark(&self.config, self.sharks.len());
+            self.sharks.insert(self.id_gen.gen(), shark);
+        }
         for shark in self.sharks.values_mut() {
             shark.destroy = None;
             shark.destroy_timer = None;
+            shark.grab = None;
+            shark.grab_timer = None;
         }
-        self.raft = Aabb2::ZERO
-            .extend_uniform(self.config.raft_size)
-            .extend_positive(vec2::splat(1))
-            .points()
-            .filter(|tile| tile.map(|x| x as f32).len() <= self.config.raft_size as f32 + 0.5)
-            .collect();
+        let entry = match self
+            .rotation
+            .get(self.rotation_index % self.rotation.len().max(1))
+        {
+            // Hand-made maps don't take raft mutators, so keep the map for the next round
+            Some(map::RotationEntry::File(_))
+                if matches!(
+                    self.mutator,
+                    Some(Mutator::SlipperyRaft | Mutator::TinyRaft)
+                ) =>
+            {
+                map::RotationEntry::Generate
+            }
+            entry => {
+                self.rotation_index += 1;
+                entry.cloned().unwrap_or(map::RotationEntry::Generate)
+            }
+        };
+        let map = match entry {
+            map::RotationEntry::File(name) => self.map_files[&name].clone(),
+            map::RotationEntry::Generate => {
+                let layout = self
+                    .config
+                    .raft_gen
+                    .layouts
+                    .iter()
+                    .copied()
+                    .choose(&mut thread_rng())
+                    .unwrap_or(map::Layout::Disc);
+                map::Map::generate(layout, self.config.raft_size, &self.config.raft_gen)
+            }
+        };
+        self.raft = map.raft;
+        self.raft_load.clear();
+        self.fragments.clear();
+        self.map_name = map.name;
+        let mut spawns = map.spawns.iter().copied().cycle();
         for id in self.player_pos.keys().copied() {
             let score = self.scores.entry(id).or_default();
             score.points += self.config.survival_points[0];
             score.wins += 1;
         }
+        self.lives.clear();
+        self.respawn_timers.clear();
+        self.repairs.clear();
+        self.floor_poops.clear();
+        self.flying_planks.clear();
+        self.sliding.clear();
+        self.stuns.clear();
+        self.items.clear();
+        self.effects.clear();
+        self.round_time = 0.0;
+        self.shrink_timer = 0.0;
+        self.sudden_death_at = self.server_time() + self.config.round.time as f64;
         for (&client, sender) in &mut self.senders {
             sender.send(ServerMessage::UpdateRaft(self.raft.clone()));
+            sender.send(ServerMessage::RoundTimer(self.sudden_death_at));
+            sender.send(ServerMessage::MapName(self.map_name.clone()));
 
             if self
                 .names
                 .get(&client)
                 .map_or(false, |name| name != SPECTATOR_STR)
             {
+                let spawn = spawns.next().unwrap().map(|x| x as f32) * self.config.tile_size;
                 let pos = Pos {
                     pos: vec3(
-                        thread_rng().gen_range(-1.0..=1.0),
-                        thread_rng().gen_range(-1.0..=1.0),
+                        spawn.x + thread_rng().gen_range(-1.0..=1.0),
+                        spawn.y + thread_rng().gen_range(-1.0..=1.0),
                         0.0,
                     ),
                     rot: Angle::from_degrees(thread_rng().gen_range(0.0..360.0)),
                     vel: vec3::ZERO,
                 };
                 self.player_pos.insert(client, pos);
-                sender.send(ServerMessage::YouSpawn(Spawn { pos }));
+                self.gull_pos.remove(&client);
+                self.lives.insert(client, self.config.respawn.lives);
+                sender.send(ServerMessage::YouSpawn(Spawn {
+                    pos,
+                    lives: self.config.respawn.lives,
+                }));
             }
-            sender.send(ServerMessage::JustRestarted);
+            sender.send(ServerMessage::JustRestarted(self.mutator));
             if self.reset {
                 self.reset = false;
                 self.scores.clear();
             }
             sender.send(ServerMessage::Scores(self.scores.clone()));
         }
+        for sender in self.senders.values_mut() {
+            sender.send(ServerMessage::Lives(self.lives.clone()));
+        }
 
         for (&id, &pos) in &self.player_pos {
             for (&client, sender) in &mut self.senders {
                 if client != id {
+                    sender.send(ServerMessage::GullLeft { id });
                     sender.send(ServerMessage::PlayerSpawn { id, pos });
                 }
             }
         }
     }
-    fn new(config: assets::Config) -> Self {
+    fn shrink_raft(&mut self) {
+        let Some(tile) = self
+            .raft
+            .keys()
+            .copied()
+            .max_by_key(|tile| (tile.x.pow(2) + tile.y.pow(2), tile.x, tile.y))
+        else {
+            return;
+        };
+        self.raft.remove(&tile);
+        for sender in self.senders.values_mut() {
+            sender.send(ServerMessage::Sink(tile));
+        }
+    }
+    fn can_repair(&self, client: Id, tile: vec2<i32>) -> bool {
+        let Some(pos) = self.player_pos.get(&client) else {
+            return false;
+        };
+        let center = tile.map(|x| x as f32) * self.config.tile_size;
+        !self.raft.contains_key(&tile)
+            && map::NEIGHBOURS
+                .into_iter()
+                .any(|d| self.raft.contains_key(&(tile + d)))
+            && (pos.pos.xy() - center).len() <= self.config.repair.distance
+    }
+    fn add_tile(&mut self, tile: vec2<i32>, kind: map::TileKind) {
+        self.raft.insert(tile, kind);
+        for sender in self.senders.values_mut() {
+            sender.send(ServerMessage::AddTile(tile, kind));
+        }
+    }
+    fn spawn_item(&mut self) {
+        let Some(cell) = self
+            .raft
+            .keys()
+            .flat_map(|&tile| map::NEIGHBOURS.into_iter().map(move |d| tile + d))
+            .filter(|cell| !self.raft.contains_key(cell))
+            .choose(&mut thread_rng())
+        else {
+            return;
+        };
+        let id = self.id_gen.gen();
+        let item = Item {
+            power_up: PowerUp::ALL.into_iter().choose(&mut thread_rng()).unwrap(),
+            pos: cell.map(|x| x as f32) * self.config.tile_size,
+            vel: self.config.wave.dir.normalize_or_zero() * self.config.items.drift_speed,
+        };
+        for sender in self.senders.values_mut() {
+            sender.send(ServerMessage::ItemSpawn(id, item.clone()));
+        }
+        self.items.insert(id, (item, self.config.items.lifetime));
+    }
+    fn land_poop(&mut self, pos: vec2<f32>) {
+        let victim = self
+            .player_pos
+            .iter()
+            .find(|(_, player)| (player.pos.xy() - pos).len() < self.config.poop.hit_radius)
+            .map(|(&id, _)| id);
+        if let Some(victim) = victim {
+            let time = self.config.poop.stun_time;
+            self.stuns.insert(victim, time);
+            for (&id, sender) in &mut self.senders {
+                if id == victim {
+                    sender.send(ServerMessage::YouStunned(time));
+                } else {
+                    sender.send(ServerMessage::Stunned(victim, time));
+                }
+            }
+            return;
+        }
+        let tile = pos.map(|x| (x / self.config.tile_size).round() as i32);
+        if self.raft.contains_key(&tile) {
+            let id = self.id_gen.gen();
+            self.floor_poops
+                .insert(id, (pos, self.config.poop.lifetime));
+            for sender in self.senders.values_mut() {
+                sender.send(ServerMessage::PoopOnFloor(id, pos));
+            }
+        }
+    }
+    fn land_plank(&mut self, pos: vec2<f32>) {
+        let tile = pos.map(|x| (x / self.config.tile_size).round() as i32);
+        if !self.raft.contains_key(&tile)
+            && map::NEIGHBOURS
+                .into_iter()
+                .any(|d| self.raft.contains_key(&(tile + d)))
+        {
+            self.add_tile(tile, map::TileKind::Plain);
+        }
+    }
+    fn collide_crabs(&mut self, delta_time: f32) {
+        let crabs: Vec<(Id, Pos)> = self
+            .player_pos
+            .iter()
+            .filter(|&(id, _)| !self.wait_for_teleport_ack.contains(id))
+            .map(|(&id, &pos)| (id, pos))
+            .collect();
+        let mut nudges: HashMap<Id, vec2<f32>> = HashMap::new();
+        for (i, &(a, a_pos)) in crabs.iter().enumerate() {
+            for &(b, b_pos) in &crabs[i + 1..] {
+                let delta = a_pos.pos.xy() - b_pos.pos.xy();
+                if delta.len() >= 2.0 {
+                    continue;
+                }
+                let dir = if delta == vec2::ZERO {
+                    vec2(1.0, 0.0)
+                } else {
+                    delta.normalize()
+                };
+                let pen = 2.0 - delta.len();
+                // Whoever walks into the other crab gets moved less, so bumping pushes
+                let a_push = vec2::dot(a_pos.vel.xy(), -dir).max(0.0);
+                let b_push = vec2::dot(b_pos.vel.xy(), dir).max(0.0);
+                let a_share = (b_push + 1.0) / (a_push + b_push + 2.0);
+                *nudges.entry(a).or_insert(vec2::ZERO) += dir * pen * a_share;
+                *nudges.entry(b).or_insert(vec2::ZERO) -= dir * pen * (1.0 - a_share);
+            }
+        }
+        for (id, nudge) in nudges {
+            let nudge = nudge.clamp_len(..=self.config.collide_speed * delta_time);
+            let pos = self.player_pos.get_mut(&id).unwrap();
+            pos.pos += nudge.extend(0.0);
+            // Relative, so the client can apply it on top of its own movement without a teleport
+            if let Some(sender) = self.senders.get_mut(&id) {
+                sender.send(ServerMessage::YouCollide(nudge));
+            }
+        }
+    }
+    fn update_raft_load(&mut self, delta_time: f32) {
+        let config = &self.config.raft_physics;
+        let mut crabs: HashMap<vec2<i32>, (usize, vec2<f32>)> = HashMap::new();
+        for pos in self.player_pos.values() {
+            let tile = pos
+                .pos
+                .xy()
+                .map(|x| (x / self.config.tile_size).round() as i32);
+            let offset = pos.pos.xy() - tile.map(|x| x as f32) * self.config.tile_size;
+            let (count, sum) = crabs.entry(tile).or_insert((0, vec2::ZERO));
+            *count += 1;
+            *sum += offset;
+        }
+        let k = (config.response * delta_time).min(1.0);
+        for &tile in self.raft.keys() {
+            let target = match crabs.get(&tile) {
+                Some(&(count, sum)) => map::TileLoad {
+                    sag: count as f32 * config.sag_per_crab,
+                    tilt: (sum / count as f32 / (self.config.tile_size / 2.0)).clamp_len(..=1.0),
+                },
+                None => map::TileLoad::NONE,
+            };
+            let load = self.raft_load.entry(tile).or_insert(map::TileLoad::NONE);
+            load.sag += (target.sag - load.sag) * k;
+            load.tilt += (target.tilt - load.tilt) * k;
+        }
+        self.raft_load.retain(|tile, load| {
+            self.raft.contains_key(tile) && (load.sag > 1e-3 || load.tilt.len() > 1e-3)
+        });
+        let sunk: Vec<vec2<i32>> = self
+            .raft_load
+            .iter()
+            .filter(|(_, load)| load.sag > config.sink_depth)
+            .map(|(&tile, _)| tile)
+            .collect();
+        for tile in sunk {
+            self.raft.remove(&tile);
+            self.raft_load.remove(&tile);
+            for sender in self.senders.values_mut() {
+                sender.send(ServerMessage::Sink(tile));
+            }
+        }
+    }
+    fn detach_fragments(&mut self) {
+        let mut components = map::components(&self.raft);
+        if components.len() <= 1 {
+            return;
+        }
+        components.sort_by_key(|component| component.len());
+        components.pop();
+        let vel = self.config.wave.dir.normalize_or_zero()
+            * self.config.raft_physics.fragment_drift_speed;
+        for component in components {
+            let fragment = map::Fragment {
+                tiles: component
+                    .into_iter()
+                    .filter_map(|tile| Some((tile, self.raft.remove(&tile)?)))
+                    .collect(),
+                offset: vec2::ZERO,
+                vel,
+            };
+            let id = self.id_gen.gen();
+            for sender in self.senders.values_mut() {
+                sender.send(ServerMessage::Detach(id, fragment.clone()));
+            }
+            self.fragments
+                .insert(id, (fragment, self.config.raft_physics.fragment_lifetime));
+        }
+    }
+    fn on_raft(&self, pos: vec2<f32>) -> bool {
+        let tile = pos.map(|x| (x / self.config.tile_size).round() as i32);
+        self.raft.contains_key(&tile)
+            || self
+                .fragments
+                .values()
+                .any(|(fragment, _)| fragment.contains(pos, self.config.tile_size))
+    }
+    fn snapshot_for(&self, client: Id) -> Snapshot {
+        Snapshot {
+            time: self.server_time(),
+            players: self
+                .player_pos
+                .iter()
+                .filter(|&(&id, _)| id != client)
+                .map(|(&id, &pos)| (id, pos))
+                .collect(),
+            gulls: self
+                .gull_pos
+                .iter()
+                .filter(|&(&id, _)| id != client)
+                .map(|(&id, &pos)| (id, pos))
+                .collect(),
+            sharks: self.sharks.clone(),
+            raft_load: self.raft_load.clone(),
+        }
+    }
+    fn send_snapshots(&mut self, delta_time: f32) {
+        let due: Vec<Id> = self
+            .snapshot_streams
+            .iter_mut()
+            .filter_map(|(&client, stream)| {
+                stream.timer += delta_time;
+                (stream.timer >= stream.interval).then_some(client)
+            })
+            .collect();
+        for client in due {
+            let snapshot = self.snapshot_for(client);
+            let size = bincode::serialized_size(&snapshot).unwrap_or(1);
+            let bandwidth_rate = self.config.snapshot.max_bytes_per_second / size as f32;
+            let stream = self.snapshot_streams.get_mut(&client).unwrap();
+            stream.timer = (stream.timer - stream.interval).min(stream.interval);
+            stream.interval = 1.0
+                / stream
+                    .requested_rate
+                    .min(bandwidth_rate)
+                    .max(self.config.snapshot.min_rate);
+            if let Some(sender) = self.senders.get_mut(&client) {
+                sender.send(ServerMessage::Snapshot(snapshot));
+            }
+        }
+    }
+    fn server_time(&self) -> f64 {
+        self.clock.elapsed().as_secs_f64()
+    }
+    fn alive(&self) -> usize {
+        self.lives.values().filter(|&&lives| lives > 0).count()
+    }
+    fn safe_spawn_tile(&self) -> Option<vec2<i32>> {
+        let threatened: HashSet<vec2<i32>> = self
+            .sharks
+            .values()
+            .filter_map(|shark| shark.destroy)
+            .collect();
+        let safe = |tile: &vec2<i32>| {
+            let pos = tile.map(|x| x as f32) * self.config.tile_size;
+            !threatened.contains(tile)
+                && map::NEIGHBOURS
+                    .into_iter()
+                    .all(|d| self.raft.contains_key(&(*tile + d)))
+                && self
+                    .player_pos
+                    .values()
+                    .all(|other| (other.pos.xy() - pos).len() > 2.0)
+        };
+        self.raft
+            .keys()
+            .copied()
+            .filter(safe)
+            .choose(&mut thread_rng())
+            .or_else(|| {
+                self.raft
+                    .keys()
+                    .copied()
+                    .filter(|tile| !threatened.contains(tile))
+                    .choose(&mut thread_rng())
+            })
+            .or_else(|| self.raft.keys().copied().choose(&mut thread_rng()))
+    }
+    fn respawn(&mut self, client: Id) {
+        let Some(tile) = self.safe_spawn_tile() else {
+            self.lives.insert(client, 0);
+            return;
+        };
+        let pos = Pos {
+            pos: (tile.map(|x| x as f32) * self.config.tile_size).extend(0.0),
+            rot: Angle::from_degrees(thread_rng().gen_range(0.0..360.0)),
+            vel: vec3::ZERO,
+        };
+        let lives = self.lives.get(&client).copied().unwrap_or(0);
+        self.player_pos.insert(client, pos);
+        self.gull_pos.remove(&client);
+        for (&id, sender) in &mut self.senders {
+            if id == client {
+                sender.send(ServerMessage::YouSpawn(Spawn { pos, lives }));
+            } else {
+                sender.send(ServerMessage::GullLeft { id: client });
+                sender.send(ServerMessage::PlayerSpawn { id: client, pos });
+            }
+        }
+    }
+    fn new(
+        config: assets::Config,
+        rotation: Vec<map::RotationEntry>,
+        map_files: HashMap<String, map::Map>,
+    ) -> Self {
         let mut id_gen = IdGen { last_id: 0 };
         Self {
+            rotation,
+            map_files,
+            rotation_index: 0,
+            map_name: String::new(),
+            repairs: default(),
+            items: default(),
+            item_timer: 0.0,
+            effects: default(),
             reset: true,
+            lives: default(),
+            respawn_timers: default(),
+            round_time: 0.0,
+            sudden_death_at: config.round.time as f64,
+            shrink_timer: 0.0,
             last_touch: default(),
             flying_poops: Vec::new(),
+            floor_poops: default(),
+            sliding: default(),
+            stuns: default(),
             poop_cooldowns: default(),
+            dive_cooldowns: default(),
+            braces: default(),
+            brace_cooldowns: default(),
+            plank_cooldowns: default(),
+            flying_planks: Vec::new(),
             names: default(),
             wait_for_teleport_ack: default(),
             attacks: default(),
@@ -116,45 +609,69 @@ impl State {
             player_pos: default(),
             senders: default(),
             raft: default(),
+            raft_load: default(),
+            fragments: default(),
             should_exit: false,
+            clock: Timer::new(),
+            pog_sent: default(),
+            snapshot_streams: default(),
+            pings: default(),
+            ping_timer: 0.0,
+            traffic: default(),
+            traffic_timer: 0.0,
             gull_pos: default(),
             sharks: (0..config.shark.count)
-                .map(|_| {
-                    let pos = thread_rng()
-                        .gen_circle(vec2::ZERO, config.raft_size as f32 * config.tile_size);
-                    (
-                        id_gen.gen(),
-                        Shark {
-                            destroy: None,
-                            destroy_timer: None,
-                            pos: Pos {
-                                pos: pos.extend(config.shark.depth),
-                                rot: thread_rng().gen(),
-                                vel: vec3::ZERO,
-                            },
-                            target_pos: pos,
-                        },
-                    )
-                })
+                .map(|i| (id_gen.gen(), new_shark(&config, i)))
                 .collect(),
             id_gen,
+            base_config: config.clone(),
+            mutator: None,
+            votes: default(),
             config,
             scores: default(),
         }
     }
-    pub fn new_player(&mut self, mut sender: Box<dyn geng::net::Sender<ServerMessage>>) -> Id {
+    pub fn new_player(&mut self, sender: Box<dyn geng::net::Sender<encoding::Packet>>) -> Id {
+        let mut sender: Box<dyn geng::net::Sender<ServerMessage>> =
+            Box::new(traffic::Metered::new(sender, self.traffic.clone()));
         let id = self.id_gen.gen();
-        sender.send(ServerMessage::Pog);
+        sender.send(ServerMessage::YourId(id));
+        sender.send(ServerMessage::Pog(self.server_time()));
+        self.pog_sent.insert(id, self.server_time());
+        sender.send(ServerMessage::CurrentMutator(self.mutator));
         for (&other_id, &pos) in &self.player_pos {
             if other_id != id {
                 sender.send(ServerMessage::PlayerSpawn { id: other_id, pos });
             }
         }
         sender.send(ServerMessage::UpdateRaft(self.raft.clone()));
-        sender.send(ServerMessage::UpdateSharks(self.sharks.clone()));
+        sender.send(ServerMessage::Snapshot(self.snapshot_for(id)));
+        self.snapshot_streams.insert(
+            id,
+            SnapshotStream {
+                requested_rate: self.config.snapshot.rate,
+                timer: 0.0,
+                interval: 1.0 / self.config.snapshot.rate,
+            },
+        );
         for (&other_id, name) in &self.names {
             sender.send(ServerMessage::Name(other_id, name.clone()));
         }
+        sender.send(ServerMessage::Lives(self.lives.clone()));
+        sender.send(ServerMessage::RoundTimer(self.sudden_death_at));
+        sender.send(ServerMessage::MapName(self.map_name.clone()));
+        for (&poop_id, &(pos, _)) in &self.floor_poops {
+            sender.send(ServerMessage::PoopOnFloor(poop_id, pos));
+        }
+        for (&repairer, &(tile, _)) in &self.repairs {
+            sender.send(ServerMessage::StartRepair(repairer, tile));
+        }
+        for (&item_id, (item, _)) in &self.items {
+            sender.send(ServerMessage::ItemSpawn(item_id, item.clone()));
+        }
+        for (&fragment_id, (fragment, _)) in &self.fragments {
+            sender.send(ServerMessage::Detach(fragment_id, fragment.clone()));
+        }
         self.senders.insert(id, sender);
         id
     }
@@ -165,9 +682,38 @@ impl State {
             sender.send(ServerMessage::PlayerLeft { id: client });
         }
         self.gull_pos.remove(&client);
+        self.dive_cooldowns.remove(&client);
+        self.plank_cooldowns.remove(&client);
+        self.votes.remove(&client);
+        self.pog_sent.remove(&client);
+        self.snapshot_streams.remove(&client);
+        self.pings.remove(&client);
+        self.braces.remove(&client);
+        self.brace_cooldowns.remove(&client);
         self.names.remove(&client);
         self.last_touch.remove(&client);
         self.scores.remove(&client);
+        self.lives.remove(&client);
+        self.respawn_timers.remove(&client);
+        if self.repairs.remove(&client).is_some() {
+            for sender in self.senders.values_mut() {
+                sender.send(ServerMessage::StopRepair(client));
+            }
+        }
+        self.effects.remove(&client);
+        self.sliding.remove(&client);
+        self.stuns.remove(&client);
+    }
+    pub fn handle_packet(&mut self, client: Id, packet: encoding::Packet) {
+        let message = match packet.decode() {
+            Ok(message) => message,
+            Err(error) => {
+                log::warn!("Dropping a malformed message from {client}: {error}");
+                return;
+            }
+        };
+        self.traffic.lock().unwrap().received(&message, &packet);
+        self.handle(client, message);
     }
     pub fn handle(&mut self, client: Id, message: ClientMessage) {
         let sender = self.senders.get_mut(&client).unwrap();
@@ -191,6 +737,56 @@ impl State {
                     }
                 }
             }
+            ClientMessage::Dive => {
+                if self.dive_cooldowns.contains_key(&client) {
+                    return;
+                }
+                let Some(&gull) = self.gull_pos.get(&client) else {
+                    return;
+                };
+                self.dive_cooldowns
+                    .insert(client, self.config.gull.dive_cooldown);
+                let Some((&victim, _)) = self.player_pos.iter().find(|&(&id, pos)| {
+                    (pos.pos.xy() - gull.pos.xy()).len() < self.config.gull.dive_radius
+                        && !has_effect(&self.effects, id, PowerUp::Immunity)
+                }) else {
+                    return;
+                };
+                let delta = gull.vel.xy().normalize_or_zero() * self.config.gull.dive_push;
+                let pos = self.player_pos.get_mut(&victim).unwrap();
+                pos.pos += delta.extend(0.0);
+                let new_pos = *pos;
+                self.last_touch.insert(victim, (client, Timer::new()));
+                for (&id, sender) in &mut self.senders {
+                    sender.send(ServerMessage::Damage(new_pos.pos));
+                    if id == victim {
+                        sender.send(ServerMessage::YouWasPushed(delta));
+                        self.wait_for_teleport_ack.insert(victim);
+                    } else {
+                        sender.send(ServerMessage::WasPushed(victim, new_pos));
+                    }
+                }
+            }
+            ClientMessage::DropPlank => {
+                if self.plank_cooldowns.contains_key(&client) {
+                    return;
+                }
+                if let Some(&pos) = self.gull_pos.get(&client) {
+                    self.plank_cooldowns
+                        .insert(client, self.config.gull.plank_cooldown);
+                    let mut pos = pos;
+                    pos.vel = vec3::ZERO;
+                    self.flying_planks.push(pos);
+                    for client in self.senders.values_mut() {
+                        client.send(ServerMessage::FlyingPlank(pos))
+                    }
+                }
+            }
+            ClientMessage::Vote(mutator) => {
+                if self.gull_pos.contains_key(&client) {
+                    self.votes.insert(client, mutator);
+                }
+            }
             ClientMessage::Name(name) => {
                 let name = name.chars().filter(|c| c.is_ascii_alphabetic()).take(15);
                 let name: String = rustrict::CensorIter::censor(name).collect();
@@ -206,11 +802,13 @@ impl State {
                 self.names.insert(client, name);
                 self.gull_pos.remove(&client);
             }
-            ClientMessage::Attack(target) => {
-                if !self.dash_cooldowns.contains_key(&client) {
+            ClientMessage::Attack(target, charge) => {
+                if !self.dash_cooldowns.contains_key(&client) && !self.stuns.contains_key(&client) {
                     if let Some(pos) = &self.player_pos.get(&client) {
                         let dir = (target - pos.pos).xy().normalize_or_zero();
-                        self.attacks.insert(client, (dir, self.config.attack_time));
+                        let k = self.config.dash_charge.distance_k(charge);
+                        self.attacks
+                            .insert(client, (dir, self.config.attack_time, k));
                         sender.send(ServerMessage::YouStartAttack(dir));
                         for (&id, other) in &mut self.senders {
                             if id != client {
@@ -220,14 +818,46 @@ impl State {
                     }
                 }
             }
+            ClientMessage::Brace => {
+                if self.brace_cooldowns.contains_key(&client)
+                    || self.stuns.contains_key(&client)
+                    || !self.player_pos.contains_key(&client)
+                {
+                    return;
+                }
+                let time = self.config.brace.time;
+                self.braces.insert(client, time);
+                self.brace_cooldowns
+                    .insert(client, self.config.brace.cooldown);
+                for (&id, other) in &mut self.senders {
+                    if id != client {
+                        other.send(ServerMessage::Brace(client, time));
+                    }
+                }
+            }
+            ClientMessage::Repair(tile) => {
+                if self.repairs.contains_key(&client) || !self.can_repair(client, tile) {
+                    return;
+                }
+                self.repairs.insert(client, (tile, 0.0));
+                for other in self.senders.values_mut() {
+                    other.send(ServerMessage::StartRepair(client, tile));
+                }
+            }
             ClientMessage::TeleportAck => {
                 self.wait_for_teleport_ack.remove(&client);
             }
             ClientMessage::UpdateGullPos(pos) => {
-                self.gull_pos.insert(client, pos);
+                // Updates sent before the client heard about its respawn
+                if !self.player_pos.contains_key(&client) {
+                    self.gull_pos.insert(client, pos);
+                }
             }
             ClientMessage::UpdatePos(pos) => {
-                if self.wait_for_teleport_ack.contains(&client) {
+                if self.wait_for_teleport_ack.contains(&client)
+                    || self.sliding.contains_key(&client)
+                    || self.stuns.contains_key(&client)
+                {
                     return;
                 }
                 if let std::collections::hash_map::Entry::Occupied(mut e) =
@@ -236,19 +866,26 @@ impl State {
                     e.insert(pos);
                 }
             }
-            ClientMessage::Pig => {
-                sender.send(ServerMessage::Pog);
-                for (&id, &pos) in &self.player_pos {
-                    if id != client {
-                        sender.send(ServerMessage::UpdatePos { id, pos });
-                    }
-                }
-                for (&id, &pos) in &self.gull_pos {
-                    if id != client {
-                        sender.send(ServerMessage::UpdateGullPos { id, pos });
-                    }
+            ClientMessage::Pig(pog_time) => {
+                let now = self.server_time();
+                let Some(sent) = self.pog_sent.insert(client, now) else {
+                    return;
+                };
+                // Client moved our Pog time on by how long it waited, leaving only the round trip.
+                // It can't have answered before we sent it or after now, whatever it claims
+                let rtt = now - pog_time.max(sent).min(now);
+                let ping = self.pings.entry(client).or_insert(rtt);
+                *ping += (rtt - *ping) * ServerClock::SMOOTHING;
+                sender.send(ServerMessage::Pog(now));
+            }
+            ClientMessage::SnapshotRate(rate) => {
+                if let Some(stream) = self.snapshot_streams.get_mut(&client) {
+                    stream.requested_rate = if rate.is_nan() {
+                        self.config.snapshot.rate
+                    } else {
+                        rate.clamp(self.config.snapshot.min_rate, self.config.snapshot.rate)
+                    };
                 }
-                sender.send(ServerMessage::UpdateSharks(self.sharks.clone()));
             }
         }
     }
@@ -260,39 +897,140 @@ impl State {
         for poop in &mut self.flying_poops {
             poop.vel.z -= self.config.gravity * delta_time;
             poop.pos += poop.vel * delta_time;
-            if poop.pos.z <= 0.0 {
-                let tile = poop
-                    .pos
-                    .xy()
-                    .map(|x| (x / self.config.tile_size).round() as i32);
-                if self.raft.contains(&tile) {
-                    for sender in self.senders.values_mut() {
-                        sender.send(ServerMessage::PoopOnFloor(poop.pos.xy()));
-                    }
+        }
+        let landed: Vec<vec2<f32>> = self
+            .flying_poops
+            .iter()
+            .filter(|poop| poop.pos.z <= 0.0)
+            .map(|poop| poop.pos.xy())
+            .collect();
+        self.flying_poops.retain(|poop| poop.pos.z > 0.0);
+        for pos in landed {
+            self.land_poop(pos);
+        }
+        for plank in &mut self.flying_planks {
+            plank.vel.z -= self.config.gravity * delta_time;
+            plank.pos += plank.vel * delta_time;
+        }
+        let landed: Vec<vec2<f32>> = self
+            .flying_planks
+            .iter()
+            .filter(|plank| plank.pos.z <= 0.0)
+            .map(|plank| plank.pos.xy())
+            .collect();
+        self.flying_planks.retain(|plank| plank.pos.z > 0.0);
+        for pos in landed {
+            self.land_plank(pos);
+        }
+
+        for (_, lifetime) in self.floor_poops.values_mut() {
+            *lifetime -= delta_time;
+        }
+        self.floor_poops.retain(|&id, &mut (pos, lifetime)| {
+            let tile = pos.map(|x| (x / self.config.tile_size).round() as i32);
+            if lifetime > 0.0 && self.raft.contains_key(&tile) {
+                true
+            } else {
+                for sender in self.senders.values_mut() {
+                    sender.send(ServerMessage::PoopGone(id));
                 }
+                false
             }
+        });
+
+        for time in self.stuns.values_mut() {
+            *time -= delta_time;
         }
-        self.flying_poops.retain(|poop| poop.pos.z > 0.0);
+        self.stuns.retain(|_, time| *time > 0.0);
+
+        for (&client, pos) in &mut self.player_pos {
+            let tile = pos
+                .pos
+                .xy()
+                .map(|x| (x / self.config.tile_size).round() as i32);
+            let on_poop = self
+                .floor_poops
+                .values()
+                .any(|&(poop, _)| (poop - pos.pos.xy()).len() < 3.0)
+                || self.raft.get(&tile) == Some(&map::TileKind::Slippery);
+            if on_poop {
+                let vel = *self
+                    .sliding
+                    .entry(client)
+                    .or_insert_with(|| pos.slide_start(self.config.slide_speed));
+                pos.slide(vel, delta_time);
+            } else if self.sliding.remove(&client).is_some() {
+                if let Some(sender) = self.senders.get_mut(&client) {
+                    sender.send(ServerMessage::YouStopSliding(pos.pos));
+                    self.wait_for_teleport_ack.insert(client);
+                }
+            }
+        }
+
+        self.ping_timer += delta_time;
+        if self.ping_timer > 1.0 {
+            self.ping_timer = 0.0;
+            let pings: HashMap<Id, f32> = self
+                .pings
+                .iter()
+                .map(|(&id, &ping)| (id, ping as f32))
+                .collect();
+            for sender in self.senders.values_mut() {
+                sender.send(ServerMessage::Pings(pings.clone()));
+            }
+        }
+
+        self.traffic_timer += delta_time;
+        if self.traffic_timer > Self::TRAFFIC_REPORT_INTERVAL {
+            self.traffic.lock().unwrap().report(self.traffic_timer);
+            self.traffic_timer = 0.0;
+        }
+
+        self.collide_crabs(delta_time);
+        self.update_raft_load(delta_time);
+
+        for (fragment, lifetime) in self.fragments.values_mut() {
+            let shift = fragment.vel * delta_time;
+            // UpdatePos is ignored while sliding, stunned or teleporting, so carry crabs here
+            for pos in self.player_pos.values_mut() {
+                if fragment.contains(pos.pos.xy(), self.config.tile_size) {
+                    pos.pos += shift.extend(0.0);
+                }
+            }
+            fragment.offset += shift;
+            *lifetime -= delta_time;
+        }
+        self.fragments.retain(|&id, (_, lifetime)| {
+            if *lifetime > 0.0 {
+                true
+            } else {
+                for sender in self.senders.values_mut() {
+                    sender.send(ServerMessage::FragmentSink(id));
+                }
+                false
+            }
+        });
 
+        let alive = self.alive();
         let current_survival_points = self
             .config
             .survival_points
-            .get(self.player_pos.len().max(1) - 1)
+            .get(alive.max(1) - 1)
             .copied()
             .unwrap_or(0);
-        let win = self.player_pos.len() <= 1;
+        let win = alive <= 1;
+        let mut lives_changed = false;
         for (client, pos) in self.player_pos.clone() {
             if Aabb2::ZERO
                 .extend_uniform(1)
                 .extend_positive(vec2::splat(1))
                 .points()
-                .all(|p| {
-                    let tile = (pos.pos.xy() + p.map(|x| x as f32))
-                        .map(|x| (x / self.config.tile_size).round() as i32);
-                    !self.raft.contains(&tile)
-                })
+                .all(|p| !self.on_raft(pos.pos.xy() + p.map(|x| x as f32)))
             {
                 self.player_pos.remove(&client);
+                self.effects.remove(&client);
+                self.sliding.remove(&client);
+                self.stuns.remove(&client);
                 if let Some((attacker, timer)) = self.last_touch.remove(&client) {
                     if timer.elapsed().as_secs_f64() < self.config.kill_timer {
                         let score = self.scores.entry(attacker).or_default();
@@ -300,10 +1038,18 @@ impl State {
                         score.points += self.config.elimination_points;
                     }
                 }
-                let score = self.scores.entry(client).or_default();
-                score.points += current_survival_points;
-                if win {
-                    score.wins += 1;
+                let lives = self.lives.entry(client).or_default();
+                *lives = lives.saturating_sub(1);
+                lives_changed = true;
+                if *lives > 0 {
+                    self.respawn_timers
+                        .insert(client, self.config.respawn.delay);
+                } else {
+                    let score = self.scores.entry(client).or_default();
+                    score.points += current_survival_points;
+                    if win {
+                        score.wins += 1;
+                    }
                 }
                 if let Some(sender) = self.senders.get_mut(&client) {
                     sender.send(ServerMessage::YouDrown);
@@ -317,6 +1063,97 @@ impl State {
             }
         }
 
+        for time in self.respawn_timers.values_mut() {
+            *time -= delta_time;
+        }
+        let respawned: Vec<Id> = self
+            .respawn_timers
+            .iter()
+            .filter(|&(_, &time)| time <= 0.0)
+            .map(|(&client, _)| client)
+            .collect();
+        for client in respawned {
+            self.respawn_timers.remove(&client);
+            self.respawn(client);
+            lives_changed = true;
+        }
+        if lives_changed {
+            for sender in self.senders.values_mut() {
+                sender.send(ServerMessage::Lives(self.lives.clone()));
+            }
+        }
+
+        self.item_timer += delta_time;
+        if self.item_timer > self.config.items.spawn_interval {
+            self.item_timer = 0.0;
+            if self.items.len() < self.config.items.max_count {
+                self.spawn_item();
+            }
+        }
+        for (item, lifetime) in self.items.values_mut() {
+            item.pos += item.vel * delta_time;
+            *lifetime -= delta_time;
+        }
+        let mut gone = Vec::new();
+        for (&item_id, (item, lifetime)) in &self.items {
+            if *lifetime < 0.0 {
+                gone.push(item_id);
+                continue;
+            }
+            let Some((&client, _)) = self
+                .player_pos
+                .iter()
+                .find(|(_, pos)| (pos.pos.xy() - item.pos).len() < self.config.items.pickup_radius)
+            else {
+                continue;
+            };
+            gone.push(item_id);
+            self.effects
+                .entry(client)
+                .or_default()
+                .insert(item.power_up, self.config.items.effect_time);
+            if let Some(sender) = self.senders.get_mut(&client) {
+                sender.send(ServerMessage::YouPickUp(
+                    item.power_up,
+                    self.config.items.effect_time,
+                ));
+            }
+        }
+        for item_id in gone {
+            self.items.remove(&item_id);
+            for sender in self.senders.values_mut() {
+                sender.send(ServerMessage::ItemGone(item_id));
+            }
+        }
+        for effects in self.effects.values_mut() {
+            for time in effects.values_mut() {
+                *time -= delta_time;
+            }
+            effects.retain(|_, time| *time > 0.0);
+        }
+
+        for (_, time) in self.repairs.values_mut() {
+            *time += delta_time;
+        }
+        let repairs: Vec<(Id, vec2<i32>, f32)> = self
+            .repairs
+            .iter()
+            .map(|(&client, &(tile, time))| (client, tile, time))
+            .collect();
+        for (client, tile, time) in repairs {
+            let can_repair = self.can_repair(client, tile);
+            if can_repair && time < self.config.repair.time {
+                continue;
+            }
+            self.repairs.remove(&client);
+            if can_repair {
+                self.add_tile(tile, map::TileKind::Plain);
+            }
+            for sender in self.senders.values_mut() {
+                sender.send(ServerMessage::StopRepair(client));
+            }
+        }
+
         for time in self.dash_cooldowns.values_mut() {
             *time -= delta_time;
         }
@@ -343,16 +1180,62 @@ impl State {
                 false
             }
         });
+        for time in self.braces.values_mut() {
+            *time -= delta_time;
+        }
+        self.braces.retain(|_, time| *time > 0.0);
+        for time in self.brace_cooldowns.values_mut() {
+            *time -= delta_time;
+        }
+        self.brace_cooldowns.retain(|&client, &mut time| {
+            if time > 0.0 {
+                true
+            } else {
+                if let Some(sender) = self.senders.get_mut(&client) {
+                    sender.send(ServerMessage::BraceRestore);
+                }
+                false
+            }
+        });
+        for time in self.dive_cooldowns.values_mut() {
+            *time -= delta_time;
+        }
+        self.dive_cooldowns.retain(|&client, &mut time| {
+            if time > 0.0 {
+                true
+            } else {
+                if let Some(sender) = self.senders.get_mut(&client) {
+                    sender.send(ServerMessage::YouCanDive);
+                }
+                false
+            }
+        });
+        for time in self.plank_cooldowns.values_mut() {
+            *time -= delta_time;
+        }
+        self.plank_cooldowns.retain(|&client, &mut time| {
+            if time > 0.0 {
+                true
+            } else {
+                if let Some(sender) = self.senders.get_mut(&client) {
+                    sender.send(ServerMessage::YouGetPlank);
+                }
+                false
+            }
+        });
 
-        for (_, time) in self.attacks.values_mut() {
+        for (_, time, _) in self.attacks.values_mut() {
             *time -= delta_time;
         }
-        self.attacks.retain(|&client, &mut (dir, time)| {
+        self.attacks.retain(|&client, &mut (dir, time, k)| {
             if time > 0.0 {
                 true
             } else {
                 if let Some(pos) = self.player_pos.get(&client).copied() {
-                    let mut dist = self.config.dash_distance;
+                    let mut dist = self.config.dash_distance * k;
+                    if has_effect(&self.effects, client, PowerUp::LongDash) {
+                        dist *= self.config.items.long_dash_k;
+                    }
                     if let Some((id, t)) = self
                         .player_pos
                         .iter()
@@ -363,10 +1246,25 @@ impl State {
                         .min_by_key(|(_, t)| r32(*t))
                     {
                         if t < dist {
-                            let push_distance = (dist - t)
+                            let mut push_distance = (dist - t)
                                 * (1.0 - self.config.push_distance_rem_k)
-                                + self.config.push_distance * self.config.push_distance_rem_k;
+                                + self.config.push_distance * k * self.config.push_distance_rem_k;
+                            let target_tile = self.player_pos[&id]
+                                .pos
+                                .xy()
+                                .map(|x| (x / self.config.tile_size).round() as i32);
+                            if self.raft.get(&target_tile) == Some(&map::TileKind::Bouncy) {
+                                push_distance *= self.config.tiles.bouncy_push_k;
+                            }
+                            if has_effect(&self.effects, id, PowerUp::Immunity) {
+                                push_distance = 0.0;
+                            }
                             dist = t;
+                            if self.braces.contains_key(&id) {
+                                dist -= push_distance * self.config.brace.reflect_k;
+                                push_distance *= self.config.brace.push_k;
+                                self.last_touch.insert(client, (id, Timer::new()));
+                            }
                             let delta = dir * push_distance;
                             if let Some(sender) = self.senders.get_mut(&id) {
                                 sender.send(ServerMessage::YouWasPushed(delta));
@@ -391,8 +1289,11 @@ impl State {
                         self.wait_for_teleport_ack.insert(client);
                         self.player_pos.get_mut(&client).unwrap().pos = new_pos;
                         self.player_pos.get_mut(&client).unwrap().vel = dir.extend(0.0);
-                        self.dash_cooldowns
-                            .insert(client, self.config.dash_cooldown);
+                        let mut cooldown = self.config.dash_cooldown;
+                        if has_effect(&self.effects, client, PowerUp::QuickDash) {
+                            cooldown *= self.config.items.quick_dash_k;
+                        }
+                        self.dash_cooldowns.insert(client, cooldown);
                         for (&id, other) in &mut self.senders {
                             if id != client {
                                 other.send(ServerMessage::Dash(
@@ -407,27 +1308,94 @@ impl State {
             }
         });
 
+        // Same clock the HUD counts down with, tick time drifts behind it
+        let sudden_death_time = (self.server_time() - self.sudden_death_at) as f32;
+        if self.restart_timer.is_none() {
+            self.round_time += delta_time;
+            // A non-positive interval would never leave the loop below, so it disables shrinking
+            if sudden_death_time > 0.0 && self.config.round.shrink_interval > 0.0 {
+                self.shrink_timer += delta_time;
+                while self.shrink_timer > self.config.round.shrink_interval {
+                    self.shrink_timer -= self.config.round.shrink_interval;
+                    self.shrink_raft();
+                }
+            }
+        }
+        let attack_prob = (self.config.shark.attack_prob
+            + self.config.shark.aggression_growth * self.round_time as f64
+            + self.config.round.attack_prob_growth * sudden_death_time.max(0.0) as f64)
+            .min(1.0);
+
         if let Some(timer) = &mut self.restart_timer {
             *timer -= delta_time;
             if *timer < 0.0 {
                 self.restart_timer = None;
                 self.restart();
             }
-        } else if (self.player_pos.len() <= 1 && self.names.len() >= 2)
-            || (self.names.len() == 1 && self.player_pos.is_empty())
+        } else if (self.alive() <= 1 && self.names.len() >= 2)
+            || (self.names.len() == 1 && self.alive() == 0)
         {
             self.restart_timer = Some(self.config.restart_timer);
         }
+        let repelled: Vec<vec2<f32>> = self
+            .player_pos
+            .iter()
+            .filter(|&(&id, _)| has_effect(&self.effects, id, PowerUp::Repellent))
+            .map(|(_, pos)| pos.pos.xy())
+            .collect();
+        let crabs: Vec<vec2<f32>> = self.player_pos.values().map(|pos| pos.pos.xy()).collect();
+        let mut taken: HashSet<vec2<i32>> = self
+            .sharks
+            .values()
+            .filter_map(|shark| shark.destroy)
+            .collect();
         for (&shark_id, shark) in &mut self.sharks {
+            if let Some(timer) = &mut shark.grab_timer {
+                *timer += delta_time;
+                if *timer > self.config.shark.grab_windup {
+                    shark.grab_timer = None;
+                    let victim = shark.grab.take().unwrap();
+                    for sender in self.senders.values_mut() {
+                        sender.send(ServerMessage::StopGrab(shark_id));
+                    }
+                    let shark_pos = shark.pos.pos.xy();
+                    if let Some(pos) = self.player_pos.get_mut(&victim) {
+                        if (pos.pos.xy() - shark_pos).len() < self.config.shark.grab_radius {
+                            let target = shark_pos
+                                + (shark_pos - pos.pos.xy()).normalize_or_zero()
+                                    * self.config.tile_size;
+                            let delta = target - pos.pos.xy();
+                            pos.pos += delta.extend(0.0);
+                            let new_pos = *pos;
+                            self.last_touch.insert(victim, (shark_id, Timer::new()));
+                            for (&id, sender) in &mut self.senders {
+                                if id == victim {
+                                    sender.send(ServerMessage::YouWasPushed(delta));
+                                    self.wait_for_teleport_ack.insert(victim);
+                                } else {
+                                    sender.send(ServerMessage::WasPushed(victim, new_pos));
+                                }
+                            }
+                        }
+                    }
+                }
+                continue;
+            }
             if let Some(timer) = &mut shark.destroy_timer {
                 *timer += delta_time;
                 if *timer > 1.0 {
                     shark.destroy_timer = None;
                     let tile = shark.destroy.take().unwrap();
-                    self.raft.remove(&tile);
-
-                    for sender in self.senders.values_mut() {
-                        sender.send(ServerMessage::Destroy(shark_id, tile));
+                    if let Some(kind) = self.raft.get(&tile).and_then(|kind| kind.bitten()) {
+                        self.raft.insert(tile, kind);
+                        for sender in self.senders.values_mut() {
+                            sender.send(ServerMessage::Bite(shark_id, tile));
+                        }
+                    } else {
+                        self.raft.remove(&tile);
+                        for sender in self.senders.values_mut() {
+                            sender.send(ServerMessage::Destroy(shark_id, tile));
+                        }
                     }
                 }
                 continue;
@@ -436,8 +1404,33 @@ impl State {
             let delta = shark.target_pos.extend(self.config.shark.depth) - shark.pos.pos;
             shark.pos.pos += delta.clamp_len(..=delta_time * self.config.shark.speed);
             if delta.len() < 1e-5 {
+                let edge_crab = self
+                    .player_pos
+                    .iter()
+                    .find(|&(&id, pos)| {
+                        let tile = pos
+                            .pos
+                            .xy()
+                            .map(|x| (x / self.config.tile_size).round() as i32);
+                        (pos.pos.xy() - shark.pos.pos.xy()).len() < self.config.shark.grab_radius
+                            && !has_effect(&self.effects, id, PowerUp::Repellent)
+                            && map::NEIGHBOURS
+                                .into_iter()
+                                .any(|d| !self.raft.contains_key(&(tile + d)))
+                    })
+                    .map(|(&id, _)| id);
+                if let Some(victim) = edge_crab {
+                    if thread_rng().gen_bool(self.config.shark.grab_prob) {
+                        shark.grab = Some(victim);
+                        shark.grab_timer = Some(0.0);
+                        for sender in self.senders.values_mut() {
+                            sender.send(ServerMessage::AboutToGrab(shark_id, victim));
+                        }
+                        continue;
+                    }
+                }
                 if let Some(tile) = shark.destroy {
-                    if self.raft.contains(&tile) {
+                    if self.raft.contains_key(&tile) {
                         for sender in self.senders.values_mut() {
                             sender.send(ServerMessage::AboutToDestroy(shark_id, tile));
                         }
@@ -447,27 +1440,36 @@ impl State {
                     }
                     continue;
                 }
-                let bb = Aabb2::points_bounding_box(self.raft.iter().copied())
+                let bb = Aabb2::points_bounding_box(self.raft.keys().copied())
                     .map_or(Aabb2::ZERO, |bb| bb.map(|x| x as f32));
                 let center = bb.center();
                 let r = partial_max(bb.width(), bb.height()) / 2.0 * self.config.tile_size;
 
-                if thread_rng().gen_bool(self.config.shark.attack_prob) && !self.raft.is_empty() {
-                    let (tile, empty) = self
-                        .raft
-                        .iter()
-                        .copied()
-                        .filter_map(|tile| {
-                            [vec2(-1, 0), vec2(1, 0), vec2(0, -1), vec2(0, 1)]
-                                .into_iter()
-                                .map(|d| tile + d)
-                                .find(|&next| !self.raft.contains(&next))
-                                .map(|next| (tile, next))
+                let target = if thread_rng().gen_bool(attack_prob) {
+                    let targets = shark_ai::edge_targets(&self.raft)
+                        .into_iter()
+                        .filter(|(tile, _)| !taken.contains(tile))
+                        .filter(|(tile, _)| {
+                            let pos = tile.map(|x| x as f32) * self.config.tile_size;
+                            repelled.iter().all(|&repelled| {
+                                (repelled - pos).len() > self.config.items.repellent_radius
+                            })
                         })
-                        .choose(&mut thread_rng())
-                        .unwrap();
+                        .collect();
+                    shark_ai::choose_target(
+                        shark.behavior,
+                        &self.raft,
+                        targets,
+                        &crabs,
+                        self.config.tile_size,
+                    )
+                } else {
+                    None
+                };
+                if let Some((tile, empty)) = target {
                     shark.target_pos = empty.map(|x| x as f32) * self.config.tile_size;
                     shark.destroy = Some(tile);
+                    taken.insert(tile);
                 } else {
                     shark.target_pos =
                         thread_rng().gen_circle(center, r + self.config.shark.extra_move_radius);
@@ -477,21 +1479,42 @@ impl State {
                 shark.pos.rot = delta.xy().arg();
             }
         }
+
+        self.detach_fragments();
+        self.send_snapshots(delta_time);
     }
 }
 
 pub struct App {
     state: Arc<Mutex<State>>,
+    net_sim: net_sim::NetSim,
 }
 
 impl App {
     const TPS: f32 = 10.0;
-    pub fn new() -> Self {
+    pub fn new(net_sim: net_sim::NetSim) -> Self {
         let config = futures::executor::block_on(file::load_detect(
             run_dir().join("assets").join("config.toml"),
         ))
         .unwrap();
-        let state = Arc::new(Mutex::new(State::new(config)));
+        let maps_dir = run_dir().join("assets").join("maps");
+        let rotation: Vec<map::RotationEntry> =
+            futures::executor::block_on(file::load_detect(maps_dir.join("list.ron"))).unwrap();
+        let map_files = rotation
+            .iter()
+            .filter_map(|entry| match entry {
+                map::RotationEntry::File(name) => Some(name.clone()),
+                map::RotationEntry::Generate => None,
+            })
+            .map(|name| {
+                let map_file: map::MapFile = futures::executor::block_on(file::load_detect(
+                    maps_dir.join(format!("{name}.ron")),
+                ))
+                .unwrap();
+                (name, map::Map::from_file(map_file).unwrap())
+            })
+            .collect();
+        let state = Arc::new(Mutex::new(State::new(config, rotation, map_files)));
         std::thread::spawn({
             let state = state.clone();
             move || loop {
@@ -506,7 +1529,7 @@ impl App {
                 std::thread::sleep(std::time::Duration::from_secs_f32(delta_time));
             }
         });
-        Self { state }
+        Self { state, net_sim }
     }
 }
 
@@ -519,29 +1542,84 @@ impl Drop for App {
 pub struct ClientConnection {
     id: Id,
     state: Arc<Mutex<State>>,
+    incoming: Option<net_sim::Delayed<encoding::Packet>>,
 }
 
-impl geng::net::Receiver<ClientMessage> for ClientConnection {
-    fn handle(&mut self, message: ClientMessage) {
-        self.state.lock().unwrap().handle(self.id, message);
+impl geng::net::Receiver<encoding::Packet> for ClientConnection {
+    fn handle(&mut self, packet: encoding::Packet) {
+        match &self.incoming {
+            Some(incoming) => incoming.send(packet),
+            None => self.state.lock().unwrap().handle_packet(self.id, packet),
+        }
     }
 }
 
 impl Drop for ClientConnection {
     fn drop(&mut self) {
+        // Stop delayed messages first so none arrive for a player that is gone
+        self.incoming.take();
         self.state.lock().unwrap().drop_player(self.id);
     }
 }
 
 impl geng::net::server::App for App {
     type Client = ClientConnection;
-    type ServerMessage = ServerMessage;
-    type ClientMessage = ClientMessage;
-    fn connect(&mut self, sender: Box<dyn geng::net::Sender<Self::ServerMessage>>) -> Self::Client {
+    type ServerMessage = encoding::Packet;
+    type ClientMessage = encoding::Packet;
+    fn connect(
+        &mut self,
+        mut sender: Box<dyn geng::net::Sender<Self::ServerMessage>>,
+    ) -> Self::Client {
+        if self.net_sim.is_enabled() {
+            sender = Box::new(net_sim::Delayed::new(
+                self.net_sim.clone(),
+                move |message| sender.send(message),
+            ));
+        }
         let id = self.state.lock().unwrap().new_player(sender);
+        let incoming = self.net_sim.is_enabled().then(|| {
+            let state = self.state.clone();
+            net_sim::Delayed::new(self.net_sim.clone(), move |packet| {
+                state.lock().unwrap().handle_packet(id, packet)
+            })
+        });
         ClientConnection {
             id,
             state: self.state.clone(),
+            incoming,
         }
     }
 }
+
+#[test]
+fn test_stunned_crab_rides_fragment() {
+    let config: assets::Config = futures::executor::block_on(file::load_detect(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join("config.toml"),
+    ))
+    .unwrap();
+    let mut state = State::new(config, Vec::new(), HashMap::new());
+    state.sharks.clear();
+    let fragment = map::Fragment {
+        tiles: [(vec2(0, 0), map::TileKind::Plain)].into_iter().collect(),
+        offset: vec2::ZERO,
+        vel: vec2(5.0, 0.0),
+    };
+    let fragment_id = state.id_gen.gen();
+    state.fragments.insert(fragment_id, (fragment, 10.0));
+    let client = state.id_gen.gen();
+    let pos = Pos {
+        pos: vec3::ZERO,
+        rot: Angle::ZERO,
+        vel: vec3::ZERO,
+    };
+    state.player_pos.insert(client, pos);
+    state.lives.insert(client, 1);
+    state.stuns.insert(client, 10.0);
+    for _ in 0..20 {
+        state.tick(0.1);
+    }
+    let pos = state.player_pos[&client];
+    assert!((pos.pos.xy() - vec2(10.0, 0.0)).len() < 1e-3);
+}
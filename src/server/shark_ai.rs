@@ -0,0 +1,156 @@
+use super::*;
+
+pub type Target = (vec2<i32>, vec2<i32>);
+
+pub fn edge_targets(raft: &HashMap<vec2<i32>, map::TileKind>) -> Vec<Target> {
+    raft.keys()
+        .copied()
+        .filter_map(|tile| {
+            map::NEIGHBOURS
+                .into_iter()
+                .map(|d| tile + d)
+                .find(|next| !raft.contains_key(next))
+                .map(|next| (tile, next))
+        })
+        .collect()
+}
+
+/// Tiles whose removal would split their part of the raft (articulation points), in one pass
+fn cut_tiles(raft: &HashMap<vec2<i32>, map::TileKind>) -> HashSet<vec2<i32>> {
+    let mut index: HashMap<vec2<i32>, usize> = HashMap::new();
+    let mut low: HashMap<vec2<i32>, usize> = HashMap::new();
+    let mut result = HashSet::new();
+    for &root in raft.keys() {
+        if index.contains_key(&root) {
+            continue;
+        }
+        index.insert(root, index.len());
+        low.insert(root, index[&root]);
+        let mut root_children = 0;
+        // Iterative DFS: (tile, parent, next neighbour to look at)
+        let mut stack = vec![(root, None, 0)];
+        while let Some(&mut (tile, parent, ref mut next)) = stack.last_mut() {
+            if let Some(&d) = map::NEIGHBOURS.get(*next) {
+                *next += 1;
+                let neighbour = tile + d;
+                if !raft.contains_key(&neighbour) || Some(neighbour) == parent {
+                    continue;
+                }
+                if let Some(&neighbour_index) = index.get(&neighbour) {
+                    let tile_low = low[&tile].min(neighbour_index);
+                    low.insert(tile, tile_low);
+                } else {
+                    index.insert(neighbour, index.len());
+                    low.insert(neighbour, index[&neighbour]);
+                    stack.push((neighbour, Some(tile), 0));
+                }
+                continue;
+            }
+            stack.pop();
+            let Some(parent) = parent else {
+                continue;
+            };
+            let parent_low = low[&parent].min(low[&tile]);
+            low.insert(parent, parent_low);
+            if parent == root {
+                root_children += 1;
+            } else if low[&tile] >= index[&parent] {
+                result.insert(parent);
+            }
+        }
+        if root_children > 1 {
+            result.insert(root);
+        }
+    }
+    result
+}
+
+pub fn choose_target(
+    behavior: SharkBehavior,
+    raft: &HashMap<vec2<i32>, map::TileKind>,
+    targets: Vec<Target>,
+    crabs: &[vec2<f32>],
+    tile_size: f32,
+) -> Option<Target> {
+    match behavior {
+        SharkBehavior::Random => targets.into_iter().choose(&mut thread_rng()),
+        SharkBehavior::Hunter => {
+            if crabs.is_empty() {
+                return targets.into_iter().choose(&mut thread_rng());
+            }
+            targets.into_iter().min_by_key(|&(tile, _)| {
+                let pos = tile.map(|x| x as f32) * tile_size;
+                crabs
+                    .iter()
+                    .map(|&crab| r32((crab - pos).len()))
+                    .min()
+                    .unwrap()
+            })
+        }
+        SharkBehavior::Cutter => {
+            let cut_tiles = cut_tiles(raft);
+            let cuts = targets
+                .iter()
+                .copied()
+                .filter(|(tile, _)| cut_tiles.contains(tile))
+                .choose(&mut thread_rng());
+            cuts.or_else(|| targets.into_iter().choose(&mut thread_rng()))
+        }
+    }
+}
+
+#[test]
+fn test_cut_tiles() {
+    let config: assets::Config = futures::executor::block_on(file::load_detect(
+        std::path::Path::new(env!("CARGO_MANIFEST_DIR"))
+            .join("assets")
+            .join("config.toml"),
+    ))
+    .unwrap();
+    for layout in [map::Layout::Ring, map::Layout::Islands, map::Layout::Noise] {
+        let raft = map::Map::generate(layout, 5, &config.raft_gen).raft;
+        let cut_tiles = cut_tiles(&raft);
+        for &tile in raft.keys() {
+            let mut rest = raft.clone();
+            rest.remove(&tile);
+            assert_eq!(cut_tiles.contains(&tile), map::components(&rest).len() > 1);
+        }
+    }
+}
+
+#[test]
+fn test_cutter_targets_bridges() {
+    // Two 2x2 blocks joined by a single bridge tile at (2, 0)
+    let raft: HashMap<vec2<i32>, map::TileKind> = [
+        vec2(0, 0),
+        vec2(1, 0),
+        vec2(0, 1),
+        vec2(1, 1),
+        vec2(2, 0),
+        vec2(3, 0),
+        vec2(4, 0),
+        vec2(3, 1),
+        vec2(4, 1),
+    ]
+    .into_iter()
+    .map(|tile| (tile, map::TileKind::Plain))
+    .collect();
+    let bridge = [vec2(1, 0), vec2(2, 0), vec2(3, 0)];
+    for _ in 0..20 {
+        let target =
+            choose_target(SharkBehavior::Cutter, &raft, edge_targets(&raft), &[], 1.0).unwrap();
+        assert!(bridge.contains(&target.0));
+    }
+
+    // Without a bridge any edge tile will do
+    let block: HashMap<vec2<i32>, map::TileKind> =
+        raft.into_iter().filter(|(tile, _)| tile.x < 2).collect();
+    let target = choose_target(
+        SharkBehavior::Cutter,
+        &block,
+        edge_targets(&block),
+        &[],
+        1.0,
+    );
+    assert!(block.contains_key(&target.unwrap().0));
+}
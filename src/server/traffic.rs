@@ -0,0 +1,153 @@
+use super::*;
+
+#[derive(Default, Clone, Copy)]
+struct Counter {
+    messages: usize,
+    bytes: u64,
+}
+
+/// Bytes per message kind since the last report, to see what dominates bandwidth
+#[derive(Default)]
+pub struct Traffic {
+    sent: HashMap<&'static str, Counter>,
+    received: HashMap<&'static str, Counter>,
+}
+
+fn record(counters: &mut HashMap<&'static str, Counter>, kind: &'static str, bytes: usize) {
+    let counter = counters.entry(kind).or_default();
+    counter.messages += 1;
+    counter.bytes += bytes as u64;
+}
+
+impl Traffic {
+    pub fn sent(&mut self, message: &ServerMessage, packet: &encoding::Packet) {
+        record(&mut self.sent, server_message_kind(message), packet.size());
+    }
+    pub fn received(&mut self, message: &ClientMessage, packet: &encoding::Packet) {
+        record(
+            &mut self.received,
+            client_message_kind(message),
+            packet.size(),
+        );
+    }
+
+    /// Logs per second rates, biggest first, and starts counting again
+    pub fn report(&mut self, elapsed: f32) {
+        if self.sent.is_empty() && self.received.is_empty() {
+            return;
+        }
+        for (direction, counters) in [("sent", &mut self.sent), ("received", &mut self.received)] {
+            let total: u64 = counters.values().map(|counter| counter.bytes).sum();
+            log::info!("{direction}: {:.0} B/s", total as f32 / elapsed);
+            let mut counters: Vec<_> = counters.drain().collect();
+            counters.sort_by_key(|&(_, counter)| std::cmp::Reverse(counter.bytes));
+            for (kind, counter) in counters {
+                log::info!(
+                    "  {kind:<24} {:>8.0} B/s {:>6.1} msg/s",
+                    counter.bytes as f32 / elapsed,
+                    counter.messages as f32 / elapsed,
+                );
+            }
+        }
+    }
+}
+
+/// Encodes everything sent through the wrapped sender and counts the bytes
+pub struct Metered {
+    inner: Box<dyn geng::net::Sender<encoding::Packet>>,
+    traffic: Arc<Mutex<Traffic>>,
+}
+
+impl Metered {
+    pub fn new(
+        inner: Box<dyn geng::net::Sender<encoding::Packet>>,
+        traffic: Arc<Mutex<Traffic>>,
+    ) -> Self {
+        Self { inner, traffic }
+    }
+}
+
+impl geng::net::Sender<ServerMessage> for Metered {
+    fn send(&mut self, message: ServerMessage) {
+        let packet = encoding::Packet::encode(&message);
+        self.traffic.lock().unwrap().sent(&message, &packet);
+        self.inner.send(packet);
+    }
+}
+
+fn server_message_kind(message: &ServerMessage) -> &'static str {
+    match message {
+        ServerMessage::UpdateRaft(..) => "UpdateRaft",
+        ServerMessage::Snapshot(..) => "Snapshot",
+        ServerMessage::Detach(..) => "Detach",
+        ServerMessage::FragmentSink(..) => "FragmentSink",
+        ServerMessage::YouSpawn(..) => "YouSpawn",
+        ServerMessage::YouDrown => "YouDrown",
+        ServerMessage::Pog(..) => "Pog",
+        ServerMessage::YourId(..) => "YourId",
+        ServerMessage::PlayerSpawn { .. } => "PlayerSpawn",
+        ServerMessage::PlayerLeft { .. } => "PlayerLeft",
+        ServerMessage::GullLeft { .. } => "GullLeft",
+        ServerMessage::PlayerDrown(..) => "PlayerDrown",
+        ServerMessage::Destroy(..) => "Destroy",
+        ServerMessage::AboutToDestroy(..) => "AboutToDestroy",
+        ServerMessage::JustRestarted(..) => "JustRestarted",
+        ServerMessage::CurrentMutator(..) => "CurrentMutator",
+        ServerMessage::YouDash(..) => "YouDash",
+        ServerMessage::DashRestore => "DashRestore",
+        ServerMessage::YouStartAttack(..) => "YouStartAttack",
+        ServerMessage::StartAttack(..) => "StartAttack",
+        ServerMessage::Dash(..) => "Dash",
+        ServerMessage::YouWasPushed(..) => "YouWasPushed",
+        ServerMessage::YouCollide(..) => "YouCollide",
+        ServerMessage::WasPushed(..) => "WasPushed",
+        ServerMessage::Name(..) => "Name",
+        ServerMessage::Damage(..) => "Damage",
+        ServerMessage::YouCanPoopCongratulations => "YouCanPoopCongratulations",
+        ServerMessage::FlyingPoop(..) => "FlyingPoop",
+        ServerMessage::PoopOnFloor(..) => "PoopOnFloor",
+        ServerMessage::PoopGone(..) => "PoopGone",
+        ServerMessage::YouStopSliding(..) => "YouStopSliding",
+        ServerMessage::YouStunned(..) => "YouStunned",
+        ServerMessage::Stunned(..) => "Stunned",
+        ServerMessage::Scores(..) => "Scores",
+        ServerMessage::Lives(..) => "Lives",
+        ServerMessage::Pings(..) => "Pings",
+        ServerMessage::RoundTimer(..) => "RoundTimer",
+        ServerMessage::Sink(..) => "Sink",
+        ServerMessage::MapName(..) => "MapName",
+        ServerMessage::Bite(..) => "Bite",
+        ServerMessage::StartRepair(..) => "StartRepair",
+        ServerMessage::StopRepair(..) => "StopRepair",
+        ServerMessage::AddTile(..) => "AddTile",
+        ServerMessage::ItemSpawn(..) => "ItemSpawn",
+        ServerMessage::ItemGone(..) => "ItemGone",
+        ServerMessage::YouPickUp(..) => "YouPickUp",
+        ServerMessage::AboutToGrab(..) => "AboutToGrab",
+        ServerMessage::StopGrab(..) => "StopGrab",
+        ServerMessage::YouCanDive => "YouCanDive",
+        ServerMessage::Brace(..) => "Brace",
+        ServerMessage::BraceRestore => "BraceRestore",
+        ServerMessage::YouGetPlank => "YouGetPlank",
+        ServerMessage::FlyingPlank(..) => "FlyingPlank",
+    }
+}
+
+fn client_message_kind(message: &ClientMessage) -> &'static str {
+    match message {
+        ClientMessage::Pig(..) => "Pig",
+        ClientMessage::UpdatePos(..) => "UpdatePos",
+        ClientMessage::Attack(..) => "Attack",
+        ClientMessage::TeleportAck => "TeleportAck",
+        ClientMessage::Name(..) => "Name",
+        ClientMessage::UpdateGullPos(..) => "UpdateGullPos",
+        ClientMessage::Poop => "Poop",
+        ClientMessage::AdminResetSecretButton => "AdminResetSecretButton",
+        ClientMessage::Repair(..) => "Repair",
+        ClientMessage::SnapshotRate(..) => "SnapshotRate",
+        ClientMessage::Dive => "Dive",
+        ClientMessage::DropPlank => "DropPlank",
+        ClientMessage::Vote(..) => "Vote",
+        ClientMessage::Brace => "Brace",
+    }
+}